@@ -1,92 +1,553 @@
 use crate::*;
 
+/// Tokens expiring within this margin are treated as expired.
+pub const DEFAULT_TOKEN_MARGIN: time::Duration = time::Duration::minutes(10);
+
+/// Largest accepted token margin.
+///
+/// Vipps tokens are valid for an hour in the test environment, larger margins
+/// would refresh the token on every call.
+pub const MAX_TOKEN_MARGIN: time::Duration = time::Duration::minutes(15);
+
+/// Response from the access token endpoint, Vipps sends all fields as strings.
 #[derive(Clone, Debug, serde::Deserialize)]
 struct RequestTokenRes {
+    token_type: String,
     expires_in: String,
-    access_token: String,
+    ext_expires_in: String,
+    expires_on: String,
+    not_before: String,
+    resource: String,
+    access_token: Secret,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct AccessToken {
+    #[serde(with = "time::serde::rfc3339")]
     expires_on: time::OffsetDateTime,
-    token: String,
+    #[serde(with = "time::serde::rfc3339")]
+    ext_expires_on: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    not_before: time::OffsetDateTime,
+    token_type: String,
+    resource: String,
+    token: Secret,
+}
+
+fn parse_seconds(field: &str, value: &str) -> Result<i64> {
+    value
+        .parse::<i64>()
+        .map_err(|_| Error::InvalidAccessToken(format!("{field} is not a number: {value:?}")))
+}
+
+fn parse_timestamp(field: &str, value: &str) -> Result<time::OffsetDateTime> {
+    time::OffsetDateTime::from_unix_timestamp(parse_seconds(field, value)?)
+        .map_err(|_| Error::InvalidAccessToken(format!("{field} is out of range: {value:?}")))
 }
 
 impl AccessToken {
-    fn create(res: RequestTokenRes) -> Self {
-        let expires_in = res.expires_in.parse::<i64>().unwrap_or(60);
-        let expires_on = time::OffsetDateTime::now_utc() + time::Duration::seconds(expires_in);
+    fn create(res: RequestTokenRes) -> Result<Self> {
+        let expires_in = parse_seconds("expires_in", &res.expires_in)?;
+        let ext_expires_in = parse_seconds("ext_expires_in", &res.ext_expires_in)?;
+        let expires_on = parse_timestamp("expires_on", &res.expires_on)?;
+        let not_before = parse_timestamp("not_before", &res.not_before)?;
 
-        Self {
+        // The extended lifetime is relative to the regular one
+        let ext_expires_on = expires_on + time::Duration::seconds(ext_expires_in - expires_in);
+
+        Ok(Self {
             expires_on,
+            ext_expires_on,
+            not_before,
+            token_type: res.token_type,
+            resource: res.resource,
             token: res.access_token,
-        }
+        })
     }
 
+    /// Whether the token can be used for at least [`DEFAULT_TOKEN_MARGIN`].
     pub fn is_valid(&self) -> bool {
-        // We count token as invalid if it expires in less than 10 minutes
-        let now = time::OffsetDateTime::now_utc() - time::Duration::minutes(10);
-        self.expires_on > now
+        self.is_valid_with_margin(DEFAULT_TOKEN_MARGIN)
+    }
+
+    /// Whether the token can be used for at least `margin`.
+    pub fn is_valid_with_margin(&self, margin: time::Duration) -> bool {
+        let now = time::OffsetDateTime::now_utc();
+        self.not_before <= now && now + margin < self.expires_on
+    }
+
+    pub fn expires_on(&self) -> time::OffsetDateTime {
+        self.expires_on
+    }
+
+    /// Time until which Vipps may keep accepting the token during outages of
+    /// the token service.
+    pub fn ext_expires_on(&self) -> time::OffsetDateTime {
+        self.ext_expires_on
+    }
+
+    pub fn not_before(&self) -> time::OffsetDateTime {
+        self.not_before
+    }
+
+    pub fn token_type(&self) -> &str {
+        &self.token_type
+    }
+
+    pub fn resource(&self) -> &str {
+        &self.resource
     }
 
     pub fn token(&self) -> &str {
-        &self.token
+        self.token.expose()
     }
 }
 
 impl VippsApi {
-    fn client_secret_header(&self) -> reqwest::header::HeaderMap {
+    fn client_secret_header(&self) -> Result<reqwest::header::HeaderMap> {
+        let auth_info = &self.current_merchant().auth_info;
         let mut headers = reqwest::header::HeaderMap::new();
 
         headers.insert(
             "client_id",
-            (&self.0.auth_info.client_id).try_into().unwrap(),
+            merchant_header_value("client_id", auth_info.client_id())?,
         );
         headers.insert(
             "client_secret",
-            (&self.0.auth_info.client_secret).try_into().unwrap(),
+            merchant_header_value("client_secret", auth_info.client_secret())?,
         );
 
-        headers
+        Ok(headers)
+    }
+
+    /// Key for the current merchant's tokens in the token store.
+    ///
+    /// Merchants sharing credentials, like partner keys, share tokens.
+    fn token_key(&self) -> &str {
+        self.current_merchant().auth_info.client_id()
+    }
+
+    fn refresh_lock(&self) -> &Arc<tokio::sync::Mutex<()>> {
+        &self.inner.refresh_locks[self.token_key()]
     }
 
     #[tracing::instrument(skip_all, err)]
     async fn request_access_token(&self) -> Result<AccessToken> {
-        let res = self
-            .0
+        let mut merchant_headers = self.merchant_headers()?;
+        if self.current_merchant().auth_info.is_partner() {
+            // Partner tokens are shared by all merchants of the partner
+            merchant_headers.remove("Merchant-Serial-Number");
+        }
+
+        let req = self
+            .inner
             .client
-            .post(format!("{}/accesstoken/get", self.0.base_url))
-            .headers(self.client_secret_header())
+            .post(format!("{}/accesstoken/get", self.inner.base_url))
+            .headers(merchant_headers)
+            .headers(self.client_secret_header()?)
             .header("content-length", 0)
-            .body("")
-            .send()
+            .body("");
+
+        let res = self
+            .execute(req)
             .await?
             .error_for_status()?
             .json::<RequestTokenRes>()
             .await?;
 
-        tracing::debug!(token = res.access_token, "got new access token");
+        let token = AccessToken::create(res)?;
+        tracing::debug!(expires_on = %token.expires_on, "got new access token");
+
+        Ok(token)
+    }
 
-        Ok(AccessToken::create(res))
+    /// Requests a new token and puts it in the token store.
+    ///
+    /// The token is returned even if it could not be stored, the next call
+    /// will then request another one. Callers must hold `refresh_lock`.
+    async fn refresh_access_token(&self) -> Result<AccessToken> {
+        let token = self.request_access_token().await?;
+        if let Err(err) = self
+            .inner
+            .token_store
+            .set(self.token_key(), token.clone())
+            .await
+        {
+            tracing::warn!(error = %err, "failed to store access token");
+        }
+        Ok(token)
+    }
+
+    /// Refresh the token in the background unless a refresh is already in
+    /// progress.
+    fn spawn_access_token_refresh(&self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let Ok(guard) = self.refresh_lock().clone().try_lock_owned() else {
+            return;
+        };
+
+        tracing::trace!("refreshing access token in the background");
+
+        let api = self.clone();
+        runtime.spawn(async move {
+            let _guard = guard;
+
+            // A refresh may have finished after the caller read the old token
+            let margin = api.inner.token_margin * 2;
+            if let Ok(Some(token)) = api.inner.token_store.get(api.token_key()).await {
+                if token.is_valid_with_margin(margin) {
+                    return;
+                }
+            }
+
+            if let Err(err) = api.refresh_access_token().await {
+                tracing::warn!(error = %err, "background access token refresh failed");
+            }
+        });
     }
 
     #[tracing::instrument(skip_all, level = tracing::Level::DEBUG)]
     pub async fn access_token(&self) -> Result<AccessToken> {
-        if self
-            .0
-            .current_token
-            .read()
+        let key = self.token_key();
+        let margin = self.inner.token_margin;
+
+        if let Some(token) = self.inner.token_store.get(key).await? {
+            if token.is_valid_with_margin(margin) {
+                tracing::trace!("reusing previous access token");
+                if !token.is_valid_with_margin(margin * 2) {
+                    self.spawn_access_token_refresh();
+                }
+                return Ok(token);
+            }
+        }
+
+        // Only one refresh is in flight at a time, everyone else waits for it
+        // and picks up the new token from the store.
+        let _guard = self.refresh_lock().lock().await;
+
+        if let Some(token) = self.inner.token_store.get(key).await? {
+            if token.is_valid_with_margin(margin) {
+                tracing::trace!("reusing access token refreshed by another task");
+                return Ok(token);
+            }
+        }
+
+        tracing::trace!("requesting a new access token");
+        self.refresh_access_token().await
+    }
+
+    /// Get a new access token to replace one that Vipps rejected.
+    async fn renew_access_token(&self, rejected: &AccessToken) -> Result<AccessToken> {
+        let _guard = self.refresh_lock().lock().await;
+
+        if let Some(token) = self.inner.token_store.get(self.token_key()).await? {
+            if token.token() != rejected.token()
+                && token.is_valid_with_margin(self.inner.token_margin)
+            {
+                tracing::trace!("reusing access token renewed by another task");
+                return Ok(token);
+            }
+        }
+
+        tracing::trace!("requesting a new access token to replace a rejected one");
+        self.refresh_access_token().await
+    }
+
+    /// Send a request for the current merchant authorized with the current
+    /// access token.
+    ///
+    /// If Vipps responds with 401 the token is replaced and the request is
+    /// retried once with the new token.
+    pub(crate) async fn send_authorized(
+        &self,
+        req: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let req = req.headers(self.merchant_headers()?);
+        let retry_req = req.try_clone();
+        let token = self.access_token().await?;
+
+        let res = self.send_retrying(req.bearer_auth(token.token())).await?;
+
+        match retry_req {
+            Some(retry_req) if res.status() == reqwest::StatusCode::UNAUTHORIZED => {
+                tracing::debug!("access token was rejected, retrying with a new token");
+                let token = self.renew_access_token(&token).await?;
+                self.send_retrying(retry_req.bearer_auth(token.token()))
+                    .await
+            }
+            _ => Ok(res),
+        }
+    }
+
+    /// Send a request for the current merchant authorized with the client
+    /// credentials directly, as required by the checkout api.
+    pub(crate) async fn send_with_client_credentials(
+        &self,
+        req: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        self.send_retrying(
+            req.headers(self.merchant_headers()?)
+                .headers(self.client_secret_header()?),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// Fake Vipps counting token requests and answering other requests with
+    /// queued statuses, or 200 when the queue is empty.
+    #[derive(Default)]
+    struct FakeVipps {
+        token_requests: AtomicUsize,
+        statuses: Mutex<VecDeque<u16>>,
+        authorizations: Mutex<Vec<String>>,
+    }
+
+    impl FakeVipps {
+        fn token_requests(&self) -> usize {
+            self.token_requests.load(Ordering::SeqCst)
+        }
+
+        fn authorizations(&self) -> Vec<String> {
+            self.authorizations.lock().unwrap().clone()
+        }
+
+        async fn respond(&self, req: reqwest::Request) -> Result<reqwest::Response> {
+            // Let concurrent callers catch up with each other
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+            if req.url().path() == "/accesstoken/get" {
+                let n = self.token_requests.fetch_add(1, Ordering::SeqCst);
+                let now = time::OffsetDateTime::now_utc().unix_timestamp();
+                let body = serde_json::json!({
+                    "token_type": "Bearer",
+                    "expires_in": "1500",
+                    "ext_expires_in": "1500",
+                    "expires_on": (now + 1500).to_string(),
+                    "not_before": (now - 60).to_string(),
+                    "resource": "resource",
+                    "access_token": format!("token-{n}"),
+                });
+                return Ok(http::Response::new(body.to_string()).into());
+            }
+
+            let authorization = req.headers()[reqwest::header::AUTHORIZATION]
+                .to_str()
+                .unwrap()
+                .to_string();
+            self.authorizations.lock().unwrap().push(authorization);
+
+            let status = self.statuses.lock().unwrap().pop_front().unwrap_or(200);
+            Ok(http::Response::builder()
+                .status(status)
+                .body(String::new())
+                .unwrap()
+                .into())
+        }
+    }
+
+    impl Transport for FakeVipps {
+        fn execute(&self, req: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+            self.respond(req).boxed()
+        }
+    }
+
+    fn fake_api(fake: &Arc<FakeVipps>) -> VippsApiBuilder {
+        VippsApi::builder(
+            SystemInfo::new("acme-shop", "1.0.0").unwrap(),
+            MerchantInfo {
+                subscription_key: "subscription-key".into(),
+                msn: "123456".to_string(),
+            },
+            AuthInfo::Merchant {
+                client_id: "client-id".to_string(),
+                client_secret: "client-secret".into(),
+            },
+        )
+        .transport(fake.clone())
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
             .unwrap()
-            .as_ref()
-            .map(|token| !token.is_valid())
-            .unwrap_or(true)
-        {
-            tracing::trace!("requesting a new access token");
-            *self.0.current_token.write().unwrap() = Some(self.request_access_token().await?);
-        } else {
-            tracing::trace!("reusing previous access token");
+            .block_on(future)
+    }
+
+    fn token_res(expires_in: &str, expires_on: i64) -> RequestTokenRes {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        RequestTokenRes {
+            token_type: "Bearer".to_string(),
+            expires_in: expires_in.to_string(),
+            ext_expires_in: "7200".to_string(),
+            expires_on: expires_on.to_string(),
+            not_before: (now - 60).to_string(),
+            resource: "resource".to_string(),
+            access_token: Secret::new("token"),
         }
+    }
+
+    #[test]
+    fn create_access_token() {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let token = AccessToken::create(token_res("3600", now + 3600)).unwrap();
+
+        assert_eq!(token.expires_on().unix_timestamp(), now + 3600);
+        assert_eq!(token.ext_expires_on().unix_timestamp(), now + 7200);
+        assert_eq!(token.not_before().unix_timestamp(), now - 60);
+        assert_eq!(token.token_type(), "Bearer");
+        assert_eq!(token.resource(), "resource");
+        assert_eq!(token.token(), "token");
+    }
+
+    #[test]
+    fn create_access_token_invalid_number() {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let res = AccessToken::create(token_res("an hour", now + 3600));
+
+        assert!(matches!(res, Err(Error::InvalidAccessToken(_))));
+    }
+
+    #[test]
+    fn access_token_margin() {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let token = AccessToken::create(token_res("300", now + 300)).unwrap();
+
+        assert!(token.is_valid_with_margin(time::Duration::minutes(1)));
+        assert!(!token.is_valid_with_margin(time::Duration::minutes(10)));
+        assert!(!token.is_valid());
+    }
+
+    #[test]
+    fn single_token_request() {
+        let fake = Arc::new(FakeVipps::default());
+        let api = fake_api(&fake).build().unwrap();
+
+        block_on(async {
+            let calls = (0..20).map(|_| api.access_token());
+            for token in futures::future::join_all(calls).await {
+                assert_eq!(token.unwrap().token(), "token-0");
+            }
+        });
+
+        assert_eq!(fake.token_requests(), 1);
+    }
+
+    #[test]
+    fn background_token_refresh() {
+        let fake = Arc::new(FakeVipps::default());
+        // Fresh tokens are valid with the margin, but not with twice of it
+        let api = fake_api(&fake)
+            .token_margin(MAX_TOKEN_MARGIN)
+            .build()
+            .unwrap();
+
+        block_on(async {
+            assert_eq!(api.access_token().await.unwrap().token(), "token-0");
+
+            let calls = (0..20).map(|_| api.access_token());
+            for token in futures::future::join_all(calls).await {
+                assert_eq!(token.unwrap().token(), "token-0");
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            assert_eq!(api.access_token().await.unwrap().token(), "token-1");
+        });
+
+        assert_eq!(fake.token_requests(), 2);
+    }
+
+    #[test]
+    fn unstored_token_is_used() {
+        struct BrokenStore;
+
+        impl TokenStore for BrokenStore {
+            fn get<'a>(&'a self, _key: &'a str) -> BoxFuture<'a, Result<Option<AccessToken>>> {
+                futures::future::ready(Ok(None)).boxed()
+            }
+
+            fn set<'a>(&'a self, _key: &'a str, _token: AccessToken) -> BoxFuture<'a, Result<()>> {
+                let err = Error::TokenStore("disk full".to_string());
+                futures::future::ready(Err(err)).boxed()
+            }
+        }
+
+        let fake = Arc::new(FakeVipps::default());
+        let api = fake_api(&fake).token_store(BrokenStore).build().unwrap();
+
+        block_on(async {
+            assert_eq!(api.access_token().await.unwrap().token(), "token-0");
+        });
+    }
+
+    #[test]
+    fn renew_rejected_token() {
+        let fake = Arc::new(FakeVipps::default());
+        fake.statuses.lock().unwrap().push_back(401);
+        let api = fake_api(&fake).build().unwrap();
+
+        let res = block_on(async {
+            let req = api
+                .inner
+                .client
+                .get("https://apitest.vipps.no/epayment/v1/payments");
+            api.send_authorized(req).await.unwrap()
+        });
+
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert_eq!(fake.token_requests(), 2);
+        assert_eq!(fake.authorizations(), ["Bearer token-0", "Bearer token-1"]);
+    }
+
+    #[test]
+    fn renew_rejected_token_once() {
+        let fake = Arc::new(FakeVipps::default());
+        fake.statuses.lock().unwrap().extend([401, 401]);
+        let api = fake_api(&fake).build().unwrap();
+
+        let res = block_on(async {
+            let req = api
+                .inner
+                .client
+                .get("https://apitest.vipps.no/epayment/v1/payments");
+            api.send_authorized(req).await.unwrap()
+        });
+
+        assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert_eq!(fake.token_requests(), 2);
+        assert_eq!(fake.authorizations().len(), 2);
+    }
+
+    #[test]
+    fn rejected_streaming_body_is_not_retried() {
+        let fake = Arc::new(FakeVipps::default());
+        fake.statuses.lock().unwrap().push_back(401);
+        let api = fake_api(&fake).build().unwrap();
+
+        let res = block_on(async {
+            // Streaming bodies can't be cloned, so the request can't be resent
+            let req = api
+                .inner
+                .client
+                .post("https://apitest.vipps.no/epayment/v1/payments")
+                .body(reqwest::Body::wrap("{}".to_string()));
+            api.send_authorized(req).await.unwrap()
+        });
 
-        Ok(self.0.current_token.read().unwrap().clone().unwrap())
+        assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert_eq!(fake.token_requests(), 1);
+        assert_eq!(fake.authorizations(), ["Bearer token-0"]);
     }
 }
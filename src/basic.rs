@@ -1,10 +1,15 @@
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Amount {
     currency: Currency,
     value: i64,
 }
 
 impl Amount {
+    /// Amount in minor units, for example øre.
+    pub fn new(currency: Currency, value: i64) -> Self {
+        Self { currency, value }
+    }
+
     pub fn nok(value: i64) -> Self {
         Self {
             currency: Currency::Nok,
@@ -15,9 +20,13 @@ impl Amount {
     pub fn value(&self) -> i64 {
         self.value
     }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Currency {
     Nok,
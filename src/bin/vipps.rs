@@ -0,0 +1,259 @@
+//! Command line tool for inspecting and operating on payments and qrs.
+//!
+//! The client is configured with a config file given with `--config` or
+//! `VIPPS_CONFIG`, or with `VIPPS_*` environment variables, see
+//! [`VippsConfig`]. All output is json, errors are written to stderr with
+//! their causes and the details Vipps gave.
+
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use vipps_api::epayment::PaymentReference;
+use vipps_api::{Amount, Currency, VippsApi, VippsConfig};
+
+#[derive(Parser)]
+#[command(name = "vipps", version, about = "Operate on Vipps payments and qrs")]
+struct Cli {
+    /// Config file, defaults to `VIPPS_CONFIG`
+    #[arg(long, global = true)]
+    config: Option<std::path::PathBuf>,
+    /// Merchant serial number to make calls for, defaults to the configured one
+    #[arg(long, global = true)]
+    msn: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(subcommand)]
+    Payment(PaymentCommand),
+    #[command(subcommand)]
+    Qr(QrCommand),
+    /// Show information about the current access token
+    Token {
+        /// Include the token itself in the output
+        #[arg(long)]
+        show_token: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum PaymentCommand {
+    Get {
+        reference: PaymentReference,
+    },
+    Capture {
+        reference: PaymentReference,
+        #[command(flatten)]
+        amount: AmountArgs,
+    },
+    Refund {
+        reference: PaymentReference,
+        #[command(flatten)]
+        amount: AmountArgs,
+    },
+    Cancel {
+        reference: PaymentReference,
+    },
+    Events {
+        reference: PaymentReference,
+    },
+}
+
+#[derive(Subcommand)]
+enum QrCommand {
+    Create {
+        id: String,
+        redirect_url: String,
+        /// Seconds until the qr expires
+        #[arg(long)]
+        ttl: Option<u32>,
+    },
+    Get {
+        id: String,
+    },
+    List,
+    Update {
+        id: String,
+        redirect_url: String,
+    },
+    Delete {
+        id: String,
+    },
+}
+
+#[derive(clap::Args)]
+struct AmountArgs {
+    /// Amount in minor units, for example øre
+    amount: i64,
+    #[arg(long, value_enum, default_value_t = CurrencyArg::Nok)]
+    currency: CurrencyArg,
+}
+
+impl AmountArgs {
+    fn amount(&self) -> Amount {
+        let currency = match self.currency {
+            CurrencyArg::Nok => Currency::Nok,
+            CurrencyArg::Dkk => Currency::Dkk,
+            CurrencyArg::Eur => Currency::Eur,
+        };
+        Amount::new(currency, self.amount)
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CurrencyArg {
+    Nok,
+    Dkk,
+    Eur,
+}
+
+fn load_api(cli: &Cli) -> Result<VippsApi, vipps_api::Error> {
+    let config = match &cli.config {
+        Some(path) => VippsConfig::from_file(path)?,
+        None => VippsConfig::from_env()?,
+    };
+
+    let api = VippsApi::from_config(config)?;
+
+    match &cli.msn {
+        Some(msn) => api.merchant(msn),
+        None => Ok(api),
+    }
+}
+
+/// Describe an error with its sources, and the details of api errors.
+fn error_json(err: &(dyn std::error::Error + 'static)) -> serde_json::Value {
+    let mut causes = Vec::new();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+
+    let mut json = serde_json::json!({
+        "error": err.to_string(),
+        "causes": causes,
+    });
+    if let Some(vipps_api::Error::ApiError {
+        code,
+        title,
+        detail,
+    }) = err.downcast_ref()
+    {
+        json["code"] = (*code).into();
+        json["title"] = title.as_str().into();
+        json["detail"] = detail.as_str().into();
+    }
+    json
+}
+
+async fn run(cli: Cli) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let api = load_api(&cli)?;
+
+    let res = match cli.command {
+        Command::Payment(command) => match command {
+            PaymentCommand::Get { reference } => {
+                serde_json::to_value(api.payment(reference).await?.details())?
+            }
+            PaymentCommand::Capture { reference, amount } => {
+                let mut payment = api.payment(reference).await?;
+                payment.capture(amount.amount()).await?;
+                serde_json::to_value(payment.details())?
+            }
+            PaymentCommand::Refund { reference, amount } => {
+                let mut payment = api.payment(reference).await?;
+                payment.refund(amount.amount()).await?;
+                serde_json::to_value(payment.details())?
+            }
+            PaymentCommand::Cancel { reference } => {
+                let mut payment = api.payment(reference).await?;
+                payment.cancel().await?;
+                serde_json::to_value(payment.details())?
+            }
+            PaymentCommand::Events { reference } => {
+                let events = api.payment(reference).await?.events().await?;
+                serde_json::to_value(events)?
+            }
+        },
+        Command::Qr(command) => match command {
+            QrCommand::Create {
+                id,
+                redirect_url,
+                ttl,
+            } => {
+                let qr = match ttl {
+                    Some(ttl) => {
+                        api.create_redirect_qr_with_ttl(&id, &redirect_url, ttl)
+                            .await?
+                    }
+                    None => api.create_redirect_qr(&id, &redirect_url).await?,
+                };
+                serde_json::to_value(qr.data())?
+            }
+            QrCommand::Get { id } => {
+                let qr = api
+                    .get_redirect_qr(&id)
+                    .await?
+                    .ok_or_else(|| format!("qr {id:?} not found"))?;
+                serde_json::to_value(qr.data())?
+            }
+            QrCommand::List => {
+                let qrs = api.list_redirect_qrs().await?;
+                let qrs = qrs.iter().map(|qr| qr.data()).collect::<Vec<_>>();
+                serde_json::to_value(qrs)?
+            }
+            QrCommand::Update { id, redirect_url } => {
+                let mut qr = api
+                    .get_redirect_qr(&id)
+                    .await?
+                    .ok_or_else(|| format!("qr {id:?} not found"))?;
+                qr.update_redirect_url(&redirect_url).await?;
+                serde_json::to_value(qr.data())?
+            }
+            QrCommand::Delete { id } => {
+                let qr = api
+                    .get_redirect_qr(&id)
+                    .await?
+                    .ok_or_else(|| format!("qr {id:?} not found"))?;
+                qr.delete().await?;
+                serde_json::json!({ "deleted": id })
+            }
+        },
+        Command::Token { show_token } => {
+            let token = api.access_token().await?;
+            serde_json::json!({
+                "msn": api.msn(),
+                "tokenType": token.token_type(),
+                "resource": token.resource(),
+                "notBefore": token.not_before().to_string(),
+                "expiresOn": token.expires_on().to_string(),
+                "extExpiresOn": token.ext_expires_on().to_string(),
+                "valid": token.is_valid_with_margin(api.token_margin()),
+                "token": show_token.then(|| token.token()),
+            })
+        }
+    };
+
+    Ok(res)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(res) => {
+            println!("{}", serde_json::to_string_pretty(&res).unwrap());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!(
+                "{}",
+                serde_json::to_string_pretty(&error_json(&*err)).unwrap()
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Blocking client, for use outside of async code.
+//!
+//! Every client owns a single threaded tokio runtime that calls are run on.
+//! Like with `reqwest::blocking`, these types must not be used from within an
+//! async runtime.
+
+use crate::epayment::{
+    CustomerInteraction, PaymentAggregate, PaymentEvent, PaymentReference, PaymentState,
+};
+use crate::qr::QrData;
+use crate::{
+    Amount, AuthInfo, Customer, MerchantInfo, PaymentMethodType, Result, SystemInfo, UserFlow,
+};
+
+use std::sync::Arc;
+
+/// Blocking version of [`crate::VippsApi`].
+#[derive(Clone)]
+pub struct VippsApi {
+    api: crate::VippsApi,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl VippsApi {
+    pub fn new(
+        system_info: SystemInfo,
+        merchant_info: MerchantInfo,
+        auth_info: AuthInfo,
+    ) -> Result<Self> {
+        Self::from_async(crate::VippsApi::new(system_info, merchant_info, auth_info)?)
+    }
+
+    pub fn new_production(
+        system_info: SystemInfo,
+        merchant_info: MerchantInfo,
+        auth_info: AuthInfo,
+    ) -> Result<Self> {
+        Self::from_async(crate::VippsApi::new_production(
+            system_info,
+            merchant_info,
+            auth_info,
+        )?)
+    }
+
+    /// Wrap an async client, for example one made with
+    /// [`crate::VippsApi::builder`].
+    pub fn from_async(api: crate::VippsApi) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(Self {
+            api,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Get the async client this wraps.
+    pub fn as_async(&self) -> &crate::VippsApi {
+        &self.api
+    }
+
+    /// See [`crate::VippsApi::merchant`].
+    pub fn merchant(&self, msn: &str) -> Result<VippsApi> {
+        Ok(Self {
+            api: self.api.merchant(msn)?,
+            runtime: self.runtime.clone(),
+        })
+    }
+
+    pub fn msn(&self) -> &str {
+        self.api.msn()
+    }
+
+    pub fn access_token(&self) -> Result<crate::AccessToken> {
+        self.runtime.block_on(self.api.access_token())
+    }
+
+    pub fn create_payment(&self) -> CreatePaymentBuilder<'_> {
+        CreatePaymentBuilder {
+            builder: self.api.create_payment(),
+            api: self,
+        }
+    }
+
+    pub fn payment(&self, reference: PaymentReference) -> Result<Payment> {
+        let payment = self.runtime.block_on(self.api.payment(reference))?;
+        Ok(self.wrap_payment(payment))
+    }
+
+    pub fn create_redirect_qr(&self, id: &str, uri: &str) -> Result<Qr> {
+        let qr = self
+            .runtime
+            .block_on(self.api.create_redirect_qr(id, uri))?;
+        Ok(self.wrap_qr(qr))
+    }
+
+    pub fn create_redirect_qr_with_ttl(&self, id: &str, uri: &str, ttl: u32) -> Result<Qr> {
+        let qr = self
+            .runtime
+            .block_on(self.api.create_redirect_qr_with_ttl(id, uri, ttl))?;
+        Ok(self.wrap_qr(qr))
+    }
+
+    pub fn get_redirect_qr(&self, id: &str) -> Result<Option<Qr>> {
+        let qr = self.runtime.block_on(self.api.get_redirect_qr(id))?;
+        Ok(qr.map(|qr| self.wrap_qr(qr)))
+    }
+
+    pub fn list_redirect_qrs(&self) -> Result<Vec<Qr>> {
+        let qrs = self.runtime.block_on(self.api.list_redirect_qrs())?;
+        Ok(qrs.into_iter().map(|qr| self.wrap_qr(qr)).collect())
+    }
+
+    fn wrap_payment(&self, payment: crate::epayment::Payment) -> Payment {
+        Payment {
+            payment,
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    fn wrap_qr(&self, qr: crate::qr::Qr) -> Qr {
+        Qr {
+            qr,
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+/// Blocking version of [`crate::epayment::CreatePaymentBuilder`].
+pub struct CreatePaymentBuilder<'a> {
+    api: &'a VippsApi,
+    builder: crate::epayment::CreatePaymentBuilder<'a>,
+}
+
+impl<'a> CreatePaymentBuilder<'a> {
+    pub fn send(self) -> Result<Payment> {
+        let payment = self.api.runtime.block_on(self.builder.send())?;
+        Ok(self.api.wrap_payment(payment))
+    }
+
+    pub fn reference(&self) -> PaymentReference {
+        self.builder.reference()
+    }
+
+    pub fn set_amount(&mut self, amount: Amount) {
+        self.builder.set_amount(amount);
+    }
+
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.set_amount(amount);
+        self
+    }
+
+    pub fn set_customer(&mut self, customer: Customer) {
+        self.builder.set_customer(customer);
+    }
+
+    pub fn customer(mut self, customer: Customer) -> Self {
+        self.set_customer(customer);
+        self
+    }
+
+    pub fn set_customer_interaction(&mut self, customer_interaction: CustomerInteraction) {
+        self.builder.set_customer_interaction(customer_interaction);
+    }
+
+    pub fn customer_interaction(mut self, customer_interaction: CustomerInteraction) -> Self {
+        self.set_customer_interaction(customer_interaction);
+        self
+    }
+
+    pub fn set_scope(&mut self, scope: String) {
+        self.builder.set_scope(scope);
+    }
+
+    pub fn scope(mut self, scope: String) -> Self {
+        self.set_scope(scope);
+        self
+    }
+
+    pub fn set_return_url(&mut self, return_url: String) {
+        self.builder.set_return_url(return_url);
+    }
+
+    pub fn return_url(mut self, return_url: String) -> Self {
+        self.set_return_url(return_url);
+        self
+    }
+
+    pub fn set_user_flow(&mut self, user_flow: UserFlow) {
+        self.builder.set_user_flow(user_flow);
+    }
+
+    pub fn user_flow(mut self, user_flow: UserFlow) -> Self {
+        self.set_user_flow(user_flow);
+        self
+    }
+
+    pub fn set_payment_description(&mut self, payment_description: String) {
+        self.builder.set_payment_description(payment_description);
+    }
+
+    pub fn payment_description(mut self, payment_description: String) -> Self {
+        self.set_payment_description(payment_description);
+        self
+    }
+
+    pub fn set_payment_method(&mut self, payment_method: PaymentMethodType) {
+        self.builder.set_payment_method(payment_method);
+    }
+
+    pub fn payment_method(mut self, payment_method: PaymentMethodType) -> Self {
+        self.set_payment_method(payment_method);
+        self
+    }
+}
+
+/// Blocking version of [`crate::epayment::Payment`].
+#[derive(Clone)]
+pub struct Payment {
+    payment: crate::epayment::Payment,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl Payment {
+    /// Get the async payment this wraps.
+    pub fn as_async(&self) -> &crate::epayment::Payment {
+        &self.payment
+    }
+
+    pub fn reference(&self) -> PaymentReference {
+        self.payment.reference()
+    }
+
+    pub fn redirect_uri(&self) -> Option<&str> {
+        self.payment.redirect_uri()
+    }
+
+    pub fn sub(&self) -> Option<&str> {
+        self.payment.sub()
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.payment.amount()
+    }
+
+    pub fn state(&self) -> PaymentState {
+        self.payment.state()
+    }
+
+    pub fn aggregate(&self) -> PaymentAggregate {
+        self.payment.aggregate()
+    }
+
+    pub fn events(&self) -> Result<Vec<PaymentEvent>> {
+        self.runtime.block_on(self.payment.events())
+    }
+
+    pub fn cancel(&mut self) -> Result<()> {
+        self.runtime.block_on(self.payment.cancel())
+    }
+
+    pub fn capture(&mut self, amount: Amount) -> Result<()> {
+        self.runtime.block_on(self.payment.capture(amount))
+    }
+
+    pub fn refund(&mut self, amount: Amount) -> Result<()> {
+        self.runtime.block_on(self.payment.refund(amount))
+    }
+
+    pub fn update(&mut self) -> Result<()> {
+        self.runtime.block_on(self.payment.update())
+    }
+}
+
+/// Blocking version of [`crate::qr::Qr`].
+#[derive(Clone, Debug)]
+pub struct Qr {
+    qr: crate::qr::Qr,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl Qr {
+    pub fn id(&self) -> &str {
+        self.qr.id()
+    }
+
+    pub fn url(&self) -> &str {
+        self.qr.url()
+    }
+
+    pub fn redirect_url(&self) -> &str {
+        self.qr.redirect_url()
+    }
+
+    pub fn expires_at(&self) -> Option<time::OffsetDateTime> {
+        self.qr.expires_at()
+    }
+
+    pub fn data(&self) -> &QrData {
+        self.qr.data()
+    }
+
+    pub fn into_data(self) -> QrData {
+        self.qr.into_data()
+    }
+
+    pub fn update_redirect_url(&mut self, url: &str) -> Result<()> {
+        self.runtime.block_on(self.qr.update_redirect_url(url))
+    }
+
+    pub fn delete(self) -> Result<()> {
+        self.runtime.block_on(self.qr.delete())
+    }
+}
@@ -0,0 +1,537 @@
+//! Checkout api, for the hosted Vipps Checkout with card payments and
+//! address collection.
+//!
+//! See <https://developer.vippsmobilepay.com/docs/APIs/checkout-api/> for
+//! the api documentation from Vipps.
+
+use crate::*;
+
+use epayment::{CustomerInteraction, Payment, PaymentAggregate, PaymentReference};
+
+/// # Checkout api
+impl VippsApi {
+    pub fn create_checkout_session(
+        &self,
+        return_url: String,
+        callback_url: String,
+    ) -> CreateCheckoutSessionBuilder<'_> {
+        let req = CreateSessionReq {
+            merchant_info: CheckoutMerchantInfo {
+                callback_url,
+                return_url,
+                callback_authorization_token: self.create_unique_reference().into(),
+                terms_and_conditions_url: None,
+            },
+            transaction: CheckoutTransaction {
+                amount: Amount::nok(0),
+                reference: PaymentReference(self.create_unique_reference()),
+                payment_description: String::new(),
+            },
+            prefill_customer: None,
+            logistics: None,
+            configuration: CheckoutConfiguration {
+                customer_interaction: None,
+                elements: None,
+                countries: None,
+                require_user_info: None,
+                show_order_summary: None,
+            },
+            ty: "PAYMENT",
+        };
+
+        CreateCheckoutSessionBuilder { api: self, req }
+    }
+
+    #[tracing::instrument(skip_all, fields(reference = reference.as_str()), err)]
+    pub async fn checkout_session(
+        &self,
+        reference: &PaymentReference,
+    ) -> Result<CheckoutSessionStatus> {
+        let res = self
+            .send_with_client_credentials(self.inner.client.get(format!(
+                "{}/checkout/v3/session/{}",
+                self.inner.base_url, reference.0
+            )))
+            .await?
+            .into_vipps_result()
+            .await?
+            .json::<CheckoutSessionStatus>()
+            .await?;
+
+        tracing::debug!(state = ?res.session_state, "got checkout session");
+
+        Ok(res)
+    }
+}
+
+pub struct CreateCheckoutSessionBuilder<'a> {
+    api: &'a VippsApi,
+    req: CreateSessionReq,
+}
+
+impl<'a> CreateCheckoutSessionBuilder<'a> {
+    #[tracing::instrument(skip(self), err)]
+    pub async fn send(self) -> Result<CheckoutSession> {
+        let idempotency_key = self.api.create_unique_reference();
+
+        let res = self
+            .api
+            .send_with_client_credentials(
+                self.api
+                    .inner
+                    .client
+                    .post(format!("{}/checkout/v3/session", self.api.inner.base_url))
+                    .header("Idempotency-Key", &idempotency_key)
+                    .json(&self.req),
+            )
+            .await?
+            .into_vipps_result()
+            .await?
+            .json::<CreateSessionRes>()
+            .await?;
+
+        tracing::debug!(
+            reference = self.req.transaction.reference.as_str(),
+            "checkout session created"
+        );
+
+        Ok(CheckoutSession {
+            api: self.api.clone(),
+            reference: self.req.transaction.reference,
+            token: res.token,
+            checkout_frontend_url: res.checkout_frontend_url,
+            polling_url: res.polling_url,
+            callback_authorization_token: self.req.merchant_info.callback_authorization_token,
+        })
+    }
+
+    pub fn reference(&self) -> PaymentReference {
+        self.req.transaction.reference.clone()
+    }
+
+    pub fn set_amount(&mut self, amount: Amount) {
+        self.req.transaction.amount = amount;
+    }
+
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.set_amount(amount);
+        self
+    }
+
+    pub fn set_payment_description(&mut self, payment_description: String) {
+        self.req.transaction.payment_description = payment_description;
+    }
+
+    pub fn payment_description(mut self, payment_description: String) -> Self {
+        self.set_payment_description(payment_description);
+        self
+    }
+
+    pub fn set_terms_and_conditions_url(&mut self, terms_and_conditions_url: String) {
+        self.req.merchant_info.terms_and_conditions_url = Some(terms_and_conditions_url);
+    }
+
+    pub fn terms_and_conditions_url(mut self, terms_and_conditions_url: String) -> Self {
+        self.set_terms_and_conditions_url(terms_and_conditions_url);
+        self
+    }
+
+    pub fn set_prefill_customer(&mut self, customer: PrefillCustomer) {
+        self.req.prefill_customer = Some(customer);
+    }
+
+    pub fn prefill_customer(mut self, customer: PrefillCustomer) -> Self {
+        self.set_prefill_customer(customer);
+        self
+    }
+
+    pub fn set_logistics(&mut self, logistics: Logistics) {
+        self.req.logistics = Some(logistics);
+    }
+
+    pub fn logistics(mut self, logistics: Logistics) -> Self {
+        self.set_logistics(logistics);
+        self
+    }
+
+    pub fn set_customer_interaction(&mut self, customer_interaction: CustomerInteraction) {
+        self.req.configuration.customer_interaction = Some(customer_interaction);
+    }
+
+    pub fn customer_interaction(mut self, customer_interaction: CustomerInteraction) -> Self {
+        self.set_customer_interaction(customer_interaction);
+        self
+    }
+
+    pub fn set_elements(&mut self, elements: CheckoutElements) {
+        self.req.configuration.elements = Some(elements);
+    }
+
+    pub fn elements(mut self, elements: CheckoutElements) -> Self {
+        self.set_elements(elements);
+        self
+    }
+
+    /// Countries the customer can pick for the shipping address, as ISO 3166
+    /// alpha-2 codes.
+    pub fn set_supported_countries(&mut self, countries: Vec<String>) {
+        self.req.configuration.countries = Some(CheckoutCountries {
+            supported: countries,
+        });
+    }
+
+    pub fn supported_countries(mut self, countries: Vec<String>) -> Self {
+        self.set_supported_countries(countries);
+        self
+    }
+
+    pub fn set_require_user_info(&mut self, require_user_info: bool) {
+        self.req.configuration.require_user_info = Some(require_user_info);
+    }
+
+    pub fn require_user_info(mut self, require_user_info: bool) -> Self {
+        self.set_require_user_info(require_user_info);
+        self
+    }
+
+    pub fn set_show_order_summary(&mut self, show_order_summary: bool) {
+        self.req.configuration.show_order_summary = Some(show_order_summary);
+    }
+
+    pub fn show_order_summary(mut self, show_order_summary: bool) -> Self {
+        self.set_show_order_summary(show_order_summary);
+        self
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSessionReq {
+    merchant_info: CheckoutMerchantInfo,
+    transaction: CheckoutTransaction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefill_customer: Option<PrefillCustomer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logistics: Option<Logistics>,
+    configuration: CheckoutConfiguration,
+    #[serde(rename = "type")]
+    ty: &'static str,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckoutMerchantInfo {
+    callback_url: String,
+    return_url: String,
+    callback_authorization_token: Secret,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    terms_and_conditions_url: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckoutTransaction {
+    amount: Amount,
+    reference: PaymentReference,
+    payment_description: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckoutConfiguration {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    customer_interaction: Option<CustomerInteraction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elements: Option<CheckoutElements>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    countries: Option<CheckoutCountries>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    require_user_info: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    show_order_summary: Option<bool>,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckoutCountries {
+    supported: Vec<String>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSessionRes {
+    token: Secret,
+    checkout_frontend_url: String,
+    polling_url: String,
+}
+
+/// Which parts of the checkout are shown to the customer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum CheckoutElements {
+    Full,
+    PaymentAndContactInfo,
+    PaymentOnly,
+}
+
+/// Customer details to fill in for the customer.
+#[derive(Clone, Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrefillCustomer {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub street_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Logistics {
+    pub fixed_options: Vec<LogisticsOption>,
+    /// Url Vipps calls to get shipping options for the customer's address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamic_options_callback: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogisticsOption {
+    pub id: String,
+    pub brand: LogisticsBrand,
+    pub amount: Amount,
+    pub priority: i32,
+    pub is_default: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LogisticsBrand {
+    Posten,
+    Postnord,
+    Porterbuddy,
+    Instabox,
+    Helthjem,
+    Other,
+}
+
+/// A created checkout session, send the customer to
+/// [`CheckoutSession::checkout_frontend_url`] to complete it.
+#[derive(Clone)]
+pub struct CheckoutSession {
+    api: VippsApi,
+    reference: PaymentReference,
+    token: Secret,
+    checkout_frontend_url: String,
+    polling_url: String,
+    callback_authorization_token: Secret,
+}
+
+impl CheckoutSession {
+    pub fn reference(&self) -> PaymentReference {
+        self.reference.clone()
+    }
+
+    pub fn token(&self) -> &str {
+        self.token.expose()
+    }
+
+    pub fn checkout_frontend_url(&self) -> &str {
+        &self.checkout_frontend_url
+    }
+
+    pub fn polling_url(&self) -> &str {
+        &self.polling_url
+    }
+
+    /// Vipps sends this token in the `Authorization` header of callbacks for
+    /// the session.
+    pub fn callback_authorization_token(&self) -> &str {
+        self.callback_authorization_token.expose()
+    }
+
+    pub async fn status(&self) -> Result<CheckoutSessionStatus> {
+        self.api.checkout_session(&self.reference).await
+    }
+
+    /// Get the epayment payment created by the session.
+    ///
+    /// The payment only exists once the customer has initiated payment.
+    pub async fn payment(&self) -> Result<Payment> {
+        self.api.payment(self.reference.clone()).await
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CheckoutSessionState {
+    SessionCreated,
+    PaymentInitiated,
+    SessionExpired,
+    PaymentSuccessful,
+    PaymentTerminated,
+}
+
+impl CheckoutSessionState {
+    pub fn completed(&self) -> bool {
+        !matches!(
+            self,
+            CheckoutSessionState::SessionCreated | CheckoutSessionState::PaymentInitiated
+        )
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckoutSessionStatus {
+    pub session_id: String,
+    pub merchant_serial_number: Option<String>,
+    pub reference: PaymentReference,
+    pub session_state: CheckoutSessionState,
+    pub payment_method: Option<String>,
+    pub payment_details: Option<CheckoutPaymentDetails>,
+    pub user_info: Option<CheckoutUserInfo>,
+    pub shipping_details: Option<CheckoutAddressDetails>,
+    pub billing_details: Option<CheckoutAddressDetails>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckoutPaymentDetails {
+    pub amount: Amount,
+    pub state: String,
+    pub aggregate: Option<PaymentAggregate>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckoutUserInfo {
+    pub sub: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckoutAddressDetails {
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub email: Option<String>,
+    pub phone_number: Option<String>,
+    pub street_address: Option<String>,
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    /// Id of the chosen [`LogisticsOption`], only set for shipping details.
+    pub shipping_method_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_status() {
+        let address = serde_json::json!({
+            "firstName": "Ada",
+            "lastName": "Lovelace",
+            "email": "ada@example.com",
+            "phoneNumber": "4791234567",
+            "streetAddress": "Robert Levins gate 5",
+            "postalCode": "0154",
+            "city": "Oslo",
+            "country": "NO",
+            "shippingMethodId": "posten-1",
+        });
+        let json = serde_json::json!({
+            "sessionId": "v52EtjZriRsN3RrTTYzHzP",
+            "merchantSerialNumber": "123456",
+            "reference": "order-1",
+            "sessionState": "PaymentSuccessful",
+            "paymentMethod": "Wallet",
+            "paymentDetails": {
+                "amount": { "currency": "NOK", "value": 1000 },
+                "state": "AUTHORIZED",
+                "aggregate": {
+                    "authorizedAmount": { "currency": "NOK", "value": 1000 },
+                    "cancelledAmount": { "currency": "NOK", "value": 0 },
+                    "capturedAmount": { "currency": "NOK", "value": 0 },
+                    "refundedAmount": { "currency": "NOK", "value": 0 },
+                },
+            },
+            "userInfo": { "sub": "c06c4afe-d9e1-4c5d-939a-177d752a0944", "email": "ada@example.com" },
+            "shippingDetails": address,
+            "billingDetails": address,
+        });
+
+        let status = serde_json::from_value::<CheckoutSessionStatus>(json.clone()).unwrap();
+
+        assert_eq!(
+            status.session_state,
+            CheckoutSessionState::PaymentSuccessful
+        );
+        assert!(status.session_state.completed());
+        assert_eq!(
+            status.payment_details.as_ref().unwrap().amount,
+            Amount::nok(1000)
+        );
+        assert_eq!(serde_json::to_value(&status).unwrap(), json);
+    }
+
+    #[test]
+    fn created_session_status() {
+        let status = serde_json::from_value::<CheckoutSessionStatus>(serde_json::json!({
+            "sessionId": "v52EtjZriRsN3RrTTYzHzP",
+            "reference": "order-1",
+            "sessionState": "SessionCreated",
+        }))
+        .unwrap();
+
+        assert_eq!(status.session_state, CheckoutSessionState::SessionCreated);
+        assert!(!status.session_state.completed());
+        assert!(status.payment_details.is_none());
+    }
+
+    #[test]
+    fn session_states() {
+        for (state, name) in [
+            (CheckoutSessionState::SessionCreated, "SessionCreated"),
+            (CheckoutSessionState::PaymentInitiated, "PaymentInitiated"),
+            (CheckoutSessionState::SessionExpired, "SessionExpired"),
+            (CheckoutSessionState::PaymentSuccessful, "PaymentSuccessful"),
+            (CheckoutSessionState::PaymentTerminated, "PaymentTerminated"),
+        ] {
+            assert_eq!(serde_json::to_value(state).unwrap(), name);
+            assert_eq!(
+                serde_json::from_value::<CheckoutSessionState>(name.into()).unwrap(),
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn create_session_res() {
+        let res = serde_json::from_value::<CreateSessionRes>(serde_json::json!({
+            "token": "session-token",
+            "checkoutFrontendUrl": "https://vippscheckout.vipps.no/v1/",
+            "pollingUrl": "https://api.vipps.no/checkout/v3/session/order-1",
+        }))
+        .unwrap();
+
+        assert_eq!(res.token.expose(), "session-token");
+        assert_eq!(
+            res.checkout_frontend_url,
+            "https://vippscheckout.vipps.no/v1/"
+        );
+    }
+}
@@ -0,0 +1,386 @@
+//! Configuration loaded from files or the environment.
+//!
+//! A [`VippsConfig`] holds everything needed to build a [`VippsApi`]. It can be
+//! deserialized from json, or toml with the `toml` feature, for example:
+//!
+//! ```toml
+//! environment = "test"
+//!
+//! [system]
+//! system_name = "my-shop"
+//! system_version = "1.0.0"
+//!
+//! [merchant]
+//! msn = "123456"
+//! subscription_key = "..."
+//!
+//! [auth]
+//! type = "merchant"
+//! client_id = "..."
+//! client_secret = "..."
+//!
+//! [timeouts]
+//! request_secs = 30
+//!
+//! [retry]
+//! max_retries = 2
+//! ```
+
+use crate::*;
+
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Test,
+    Production,
+}
+
+impl Environment {
+    pub fn base_url(&self) -> &'static str {
+        match self {
+            Environment::Test => "https://apitest.vipps.no",
+            Environment::Production => "https://api.vipps.no",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Timeout for a whole request, including reading the response.
+    pub request_secs: Option<u64>,
+    pub connect_secs: Option<u64>,
+}
+
+/// How requests failing with connection errors, timeouts, 429 or 5xx
+/// responses are retried. Retries use exponential backoff, or the
+/// `Retry-After` header of 429 responses. Neither waits longer than
+/// `max_backoff_ms`.
+///
+/// Only requests that are safe to repeat are retried, that is `GET`, `PUT`
+/// and `DELETE` requests and requests with an `Idempotency-Key` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, no retries are made by default.
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff_ms: 200,
+            max_backoff_ms: 5000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_retries(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    /// How long to wait before retry number `attempt`, counting from 0.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff_ms);
+        Duration::from_millis(backoff)
+    }
+
+    pub(crate) fn can_retry(&self, req: &reqwest::Request) -> bool {
+        matches!(
+            *req.method(),
+            reqwest::Method::GET | reqwest::Method::PUT | reqwest::Method::DELETE
+        ) || req.headers().contains_key("Idempotency-Key")
+    }
+
+    /// How long to wait before retry number `attempt` after `res`.
+    pub(crate) fn retry_delay(&self, attempt: u32, res: Option<&reqwest::Response>) -> Duration {
+        res.filter(|res| res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS)
+            .and_then(|res| res.headers().get(reqwest::header::RETRY_AFTER))
+            .and_then(|value| parse_retry_after(value.to_str().ok()?))
+            .map(|delay| delay.min(Duration::from_millis(self.max_backoff_ms)))
+            .unwrap_or_else(|| self.backoff(attempt))
+    }
+
+    pub(crate) fn should_retry_status(&self, status: reqwest::StatusCode) -> bool {
+        status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+    }
+
+    pub(crate) fn should_retry_error(&self, err: &reqwest::Error) -> bool {
+        err.is_connect() || err.is_timeout()
+    }
+}
+
+/// `Retry-After` is either a number of seconds or a date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date =
+        time::OffsetDateTime::parse(value, &time::format_description::well_known::Rfc2822).ok()?;
+    let delay = date - time::OffsetDateTime::now_utc();
+    Some(delay.try_into().unwrap_or(Duration::ZERO))
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct VippsConfig {
+    #[serde(default)]
+    pub environment: Environment,
+    pub system: SystemInfo,
+    pub merchant: MerchantInfo,
+    pub auth: AuthInfo,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(format!("VIPPS_{name}")).ok()
+}
+
+fn required_env(name: &str) -> Result<String> {
+    env(name).ok_or_else(|| Error::Config(format!("VIPPS_{name} is not set")))
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str) -> Result<Option<T>> {
+    env(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| Error::Config(format!("VIPPS_{name} is invalid: {value:?}")))
+        })
+        .transpose()
+}
+
+impl VippsConfig {
+    /// Load the config from `VIPPS_*` environment variables.
+    ///
+    /// If `VIPPS_CONFIG` is set the config is instead loaded from that file,
+    /// see [`VippsConfig::from_file`]. Otherwise the variables are
+    /// `VIPPS_ENVIRONMENT` (`test` or `production`), `VIPPS_SYSTEM_NAME`,
+    /// `VIPPS_SYSTEM_VERSION`, `VIPPS_SYSTEM_PLUGIN_NAME`,
+    /// `VIPPS_SYSTEM_PLUGIN_VERSION`, `VIPPS_MSN`, `VIPPS_SUBSCRIPTION_KEY`,
+    /// `VIPPS_CLIENT_ID`, `VIPPS_CLIENT_SECRET`, `VIPPS_PARTNER`,
+    /// `VIPPS_TIMEOUT_SECS`, `VIPPS_CONNECT_TIMEOUT_SECS` and
+    /// `VIPPS_MAX_RETRIES`.
+    pub fn from_env() -> Result<Self> {
+        if let Some(path) = env("CONFIG") {
+            return Self::from_file(path);
+        }
+
+        let environment = match env("ENVIRONMENT").as_deref() {
+            None | Some("test") => Environment::Test,
+            Some("production") => Environment::Production,
+            Some(other) => {
+                return Err(Error::Config(format!(
+                    "VIPPS_ENVIRONMENT is invalid: {other:?}"
+                )))
+            }
+        };
+
+        let client_id = required_env("CLIENT_ID")?;
+        let client_secret = required_env("CLIENT_SECRET")?.into();
+        let auth = if parse_env("PARTNER")?.unwrap_or(false) {
+            AuthInfo::Partner {
+                client_id,
+                client_secret,
+            }
+        } else {
+            AuthInfo::Merchant {
+                client_id,
+                client_secret,
+            }
+        };
+
+        let mut retry = RetryPolicy::default();
+        if let Some(max_retries) = parse_env("MAX_RETRIES")? {
+            retry.max_retries = max_retries;
+        }
+
+        let config = Self {
+            environment,
+            system: SystemInfo {
+                system_name: required_env("SYSTEM_NAME")?,
+                system_version: required_env("SYSTEM_VERSION")?,
+                system_plugin_name: env("SYSTEM_PLUGIN_NAME"),
+                system_plugin_version: env("SYSTEM_PLUGIN_VERSION"),
+            },
+            merchant: MerchantInfo {
+                subscription_key: required_env("SUBSCRIPTION_KEY")?.into(),
+                msn: required_env("MSN")?,
+            },
+            auth,
+            timeouts: Timeouts {
+                request_secs: parse_env("TIMEOUT_SECS")?,
+                connect_secs: parse_env("CONNECT_TIMEOUT_SECS")?,
+            },
+            retry,
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_json_str(data: &str) -> Result<Self> {
+        let config: Self =
+            serde_json::from_str(data).map_err(|err| Error::Config(err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(data: &str) -> Result<Self> {
+        let config: Self = toml::from_str(data).map_err(|err| Error::Config(err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that the system info, merchant info and credentials can be sent
+    /// to Vipps. Values with stray whitespace, like a trailing newline from a
+    /// secret file, are rejected.
+    pub fn validate(&self) -> Result<()> {
+        self.system
+            .validate()
+            .and_then(|_| self.merchant.validate())
+            .and_then(|_| self.auth.validate())
+            .map_err(|err| Error::Config(err.to_string()))
+    }
+
+    /// Load the config from a json file, or a toml file with the `toml`
+    /// feature, depending on the file extension.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::from_json_str(&data),
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::from_toml_str(&data),
+            _ => Err(Error::Config(format!(
+                "unsupported config file {}",
+                path.display()
+            ))),
+        }
+    }
+
+    pub fn builder(self) -> VippsApiBuilder {
+        let mut builder = VippsApi::builder(self.system, self.merchant, self.auth);
+
+        builder.set_environment(self.environment);
+        if let Some(secs) = self.timeouts.request_secs {
+            builder.set_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.timeouts.connect_secs {
+            builder.set_connect_timeout(Duration::from_secs(secs));
+        }
+        builder.set_retry_policy(self.retry);
+
+        builder
+    }
+}
+
+impl VippsApi {
+    /// Create a client from the environment, see [`VippsConfig::from_env`].
+    pub fn from_env() -> Result<Self> {
+        VippsConfig::from_env()?.builder().build()
+    }
+
+    pub fn from_config(config: VippsConfig) -> Result<Self> {
+        config.builder().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: reqwest::Method, idempotency_key: Option<&str>) -> reqwest::Request {
+        let mut req = reqwest::Client::new().request(method, "https://apitest.vipps.no/");
+        if let Some(key) = idempotency_key {
+            req = req.header("Idempotency-Key", key);
+        }
+        req.build().unwrap()
+    }
+
+    fn response(status: u16, retry_after: Option<&str>) -> reqwest::Response {
+        let mut res = http::Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            res = res.header(reqwest::header::RETRY_AFTER, retry_after);
+        }
+        res.body(String::new()).unwrap().into()
+    }
+
+    #[test]
+    fn retry_idempotent_requests() {
+        let policy = RetryPolicy::with_max_retries(2);
+
+        assert!(policy.can_retry(&request(reqwest::Method::GET, None)));
+        assert!(policy.can_retry(&request(reqwest::Method::PUT, None)));
+        assert!(policy.can_retry(&request(reqwest::Method::DELETE, None)));
+        assert!(policy.can_retry(&request(reqwest::Method::POST, Some("key"))));
+        assert!(!policy.can_retry(&request(reqwest::Method::POST, None)));
+    }
+
+    #[test]
+    fn retry_delay() {
+        let policy = RetryPolicy::with_max_retries(2);
+
+        assert_eq!(
+            policy.retry_delay(1, Some(&response(429, Some("3")))),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            policy.retry_delay(1, Some(&response(429, Some("86400")))),
+            Duration::from_millis(policy.max_backoff_ms)
+        );
+        assert_eq!(
+            policy.retry_delay(
+                1,
+                Some(&response(429, Some("Wed, 21 Oct 2015 07:28:00 GMT")))
+            ),
+            Duration::ZERO
+        );
+        assert_eq!(
+            policy.retry_delay(1, Some(&response(503, Some("3")))),
+            policy.backoff(1)
+        );
+        assert_eq!(policy.retry_delay(0, None), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn config_rejects_invalid_values() {
+        let config = |msn: &str| {
+            serde_json::json!({
+                "system": { "system_name": "acme-shop", "system_version": "1.0.0" },
+                "merchant": { "msn": msn, "subscription_key": "subscription-key" },
+                "auth": { "type": "merchant", "client_id": "client-id", "client_secret": "client-secret" },
+            })
+            .to_string()
+        };
+
+        assert!(VippsConfig::from_json_str(&config("123456")).is_ok());
+        assert!(matches!(
+            VippsConfig::from_json_str(&config("123456\n")),
+            Err(Error::Config(_))
+        ));
+    }
+}
@@ -2,9 +2,159 @@
 
 use crate::*;
 
+use std::future::Future;
+
+/// The epayment api, see [`crate::mock`] for the backends implementing it.
+pub trait EpaymentApi {
+    fn initiate_payment(
+        &self,
+        request: PaymentRequest,
+    ) -> impl Future<Output = Result<PaymentDetails>> + Send;
+
+    fn get_payment(
+        &self,
+        reference: &PaymentReference,
+    ) -> impl Future<Output = Result<PaymentDetails>> + Send;
+
+    fn payment_events(
+        &self,
+        reference: &PaymentReference,
+    ) -> impl Future<Output = Result<Vec<PaymentEvent>>> + Send;
+
+    fn cancel_payment(
+        &self,
+        reference: &PaymentReference,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn capture_payment(
+        &self,
+        reference: &PaymentReference,
+        amount: Amount,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn refund_payment(
+        &self,
+        reference: &PaymentReference,
+        amount: Amount,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl EpaymentApi for VippsApi {
+    async fn initiate_payment(&self, request: PaymentRequest) -> Result<PaymentDetails> {
+        let reference = request
+            .reference
+            .clone()
+            .unwrap_or_else(|| PaymentReference(self.create_unique_reference()));
+        let builder = CreatePaymentBuilder {
+            api: self,
+            req: request.into_req(reference),
+        };
+
+        Ok(builder.send().await?.details())
+    }
+
+    async fn get_payment(&self, reference: &PaymentReference) -> Result<PaymentDetails> {
+        Ok(self.payment(reference.clone()).await?.details())
+    }
+
+    async fn payment_events(&self, reference: &PaymentReference) -> Result<Vec<PaymentEvent>> {
+        self.payment_events_inner(reference).await
+    }
+
+    async fn cancel_payment(&self, reference: &PaymentReference) -> Result<()> {
+        self.adjust_payment(reference, Adjustment::Cancel).await?;
+        Ok(())
+    }
+
+    async fn capture_payment(&self, reference: &PaymentReference, amount: Amount) -> Result<()> {
+        self.adjust_payment(reference, Adjustment::Capture(amount))
+            .await?;
+        Ok(())
+    }
+
+    async fn refund_payment(&self, reference: &PaymentReference, amount: Amount) -> Result<()> {
+        self.adjust_payment(reference, Adjustment::Refund(amount))
+            .await?;
+        Ok(())
+    }
+}
+
+/// A payment to create with [`EpaymentApi::initiate_payment`].
+#[derive(Clone, Debug)]
+pub struct PaymentRequest {
+    pub amount: Amount,
+    /// A unique reference is generated when none is given.
+    pub reference: Option<PaymentReference>,
+    pub customer: Option<Customer>,
+    pub customer_interaction: CustomerInteraction,
+    pub payment_method: PaymentMethodType,
+    pub scope: Option<String>,
+    pub return_url: Option<String>,
+    pub user_flow: UserFlow,
+    pub payment_description: Option<String>,
+}
+
+impl PaymentRequest {
+    /// A wallet payment with the same defaults as [`VippsApi::create_payment`].
+    pub fn new(amount: Amount) -> Self {
+        Self {
+            amount,
+            reference: None,
+            customer: None,
+            customer_interaction: CustomerInteraction::CustomerNotPresent,
+            payment_method: PaymentMethodType::Wallet,
+            scope: None,
+            return_url: None,
+            user_flow: UserFlow::WebRedirect,
+            payment_description: None,
+        }
+    }
+
+    pub(crate) fn into_req(self, reference: PaymentReference) -> CreatePaymentReq {
+        CreatePaymentReq {
+            amount: self.amount,
+            customer: self.customer,
+            customer_interaction: self.customer_interaction,
+            payment_method: PaymentMethod {
+                ty: self.payment_method,
+            },
+            profile: self.scope.map(|scope| ProfileScope { scope }),
+            reference,
+            return_url: self.return_url,
+            user_flow: self.user_flow,
+            payment_description: self.payment_description,
+        }
+    }
+}
+
+/// The current data of a payment, as returned by [`EpaymentApi`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentDetails {
+    pub reference: PaymentReference,
+    pub amount: Amount,
+    pub state: PaymentState,
+    pub aggregate: PaymentAggregate,
+    pub redirect_url: Option<String>,
+    pub sub: Option<String>,
+}
+
+impl From<&GetPaymentRes> for PaymentDetails {
+    fn from(data: &GetPaymentRes) -> Self {
+        Self {
+            reference: data.reference.clone(),
+            amount: data.amount.clone(),
+            state: data.state.clone(),
+            aggregate: data.aggregate.clone(),
+            redirect_url: data.redirect_url.clone(),
+            sub: data.profile.sub.clone(),
+        }
+    }
+}
+
 /// # Epayment api
 impl VippsApi {
-    pub fn create_payment(&self) -> CreatePaymentBuilder {
+    pub fn create_payment(&self) -> CreatePaymentBuilder<'_> {
         let req = CreatePaymentReq {
             amount: Amount::nok(0),
             customer: None,
@@ -17,21 +167,17 @@ impl VippsApi {
             payment_description: None,
         };
 
-        CreatePaymentBuilder { api: &self, req }
+        CreatePaymentBuilder { api: self, req }
     }
 
     #[cfg(not(feature = "mock"))]
     #[tracing::instrument(skip_all, fields(reference = reference.as_str()), err)]
     pub async fn payment(&self, reference: PaymentReference) -> Result<Payment> {
         let data = self
-            .0
-            .client
-            .get(format!(
+            .send_authorized(self.inner.client.get(format!(
                 "{}/epayment/v1/payments/{}",
-                self.0.base_url, reference.0
-            ))
-            .bearer_auth(self.access_token().await?.token())
-            .send()
+                self.inner.base_url, reference.0
+            )))
             .await?
             .into_vipps_result()
             .await?
@@ -44,22 +190,110 @@ impl VippsApi {
             data,
         })
     }
+
+    #[cfg(not(feature = "mock"))]
+    async fn payment_events_inner(
+        &self,
+        reference: &PaymentReference,
+    ) -> Result<Vec<PaymentEvent>> {
+        Ok(self
+            .send_authorized(self.inner.client.get(format!(
+                "{}/epayment/v1/payments/{}/events",
+                self.inner.base_url, reference.0
+            )))
+            .await?
+            .into_vipps_result()
+            .await?
+            .json::<Vec<PaymentEvent>>()
+            .await?)
+    }
+
+    #[cfg(not(feature = "mock"))]
+    async fn adjust_payment(
+        &self,
+        reference: &PaymentReference,
+        adjustment: Adjustment,
+    ) -> Result<AdjustmentRes> {
+        let idempotency_key = self.create_unique_reference();
+        let req = self
+            .inner
+            .client
+            .post(format!(
+                "{}/epayment/v1/payments/{}/{}",
+                self.inner.base_url,
+                reference.0,
+                adjustment.action()
+            ))
+            .header("Idempotency-Key", &idempotency_key);
+
+        let req = match adjustment {
+            Adjustment::Cancel => req.header("Content-Length", 0),
+            Adjustment::Capture(amount) | Adjustment::Refund(amount) => {
+                req.json(&ModificationReq {
+                    modification_amount: amount,
+                })
+            }
+        };
+
+        Ok(self
+            .send_authorized(req)
+            .await?
+            .into_vipps_result()
+            .await?
+            .json::<AdjustmentRes>()
+            .await?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Adjustment {
+    Cancel,
+    Capture(Amount),
+    Refund(Amount),
+}
+
+impl Adjustment {
+    fn action(&self) -> &'static str {
+        match self {
+            Adjustment::Cancel => "cancel",
+            Adjustment::Capture(_) => "capture",
+            Adjustment::Refund(_) => "refund",
+        }
+    }
 }
 
 #[derive(Clone, Debug, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-struct CreatePaymentReq {
-    amount: Amount,
+pub(crate) struct CreatePaymentReq {
+    pub(crate) amount: Amount,
     customer: Option<Customer>,
     customer_interaction: CustomerInteraction,
     payment_method: PaymentMethod,
     profile: Option<ProfileScope>,
-    reference: PaymentReference,
-    return_url: Option<String>,
+    pub(crate) reference: PaymentReference,
+    pub(crate) return_url: Option<String>,
     user_flow: UserFlow,
     payment_description: Option<String>,
 }
 
+impl CreatePaymentReq {
+    /// The payment as it is right after creation, with the given redirect url.
+    pub(crate) fn created_payment(&self, redirect_url: Option<String>) -> GetPaymentRes {
+        GetPaymentRes {
+            aggregate: PaymentAggregate::empty(self.amount.currency()),
+            amount: self.amount.clone(),
+            state: PaymentState::Created,
+            payment_method: PaymentMethodResponse {
+                ty: self.payment_method.ty.clone(),
+                card_bin: None,
+            },
+            profile: ProfileSub { sub: None },
+            redirect_url,
+            reference: self.reference.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, serde::Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum CustomerInteraction {
@@ -114,17 +348,16 @@ impl<'a> CreatePaymentBuilder<'a> {
     pub async fn send(self) -> Result<Payment> {
         let idempotency_key = self.api.create_unique_reference();
 
-        // TODO: retry system
-
         let res = self
             .api
-            .0
-            .client
-            .post(format!("{}/epayment/v1/payments", self.api.0.base_url))
-            .header("Idempotency-Key", &idempotency_key)
-            .bearer_auth(self.api.access_token().await?.token())
-            .json(&self.req)
-            .send()
+            .send_authorized(
+                self.api
+                    .inner
+                    .client
+                    .post(format!("{}/epayment/v1/payments", self.api.inner.base_url))
+                    .header("Idempotency-Key", &idempotency_key)
+                    .json(&self.req),
+            )
             .await?
             .into_vipps_result()
             .await?
@@ -133,21 +366,11 @@ impl<'a> CreatePaymentBuilder<'a> {
 
         tracing::debug!(reference = res.reference.as_str(), "payment created");
 
-        return Ok(Payment {
+        Ok(Payment {
             api: self.api.clone(),
-            reference: res.reference.clone(),
-            data: GetPaymentRes {
-                amount: self.req.amount,
-                state: PaymentState::Created,
-                payment_method: PaymentMethodResponse {
-                    ty: self.req.payment_method.ty,
-                    card_bin: None,
-                },
-                profile: ProfileSub { sub: None },
-                redirect_url: res.redirect_url,
-                reference: res.reference,
-            },
-        });
+            reference: res.reference,
+            data: self.req.created_payment(res.redirect_url),
+        })
     }
 
     pub fn reference(&self) -> PaymentReference {
@@ -255,26 +478,30 @@ impl Payment {
         self.data.state.clone()
     }
 
-    #[cfg(not(feature = "mock"))]
+    /// Sums of the amounts authorized, captured, refunded and cancelled.
+    pub fn aggregate(&self) -> PaymentAggregate {
+        self.data.aggregate.clone()
+    }
+
+    pub fn details(&self) -> PaymentDetails {
+        PaymentDetails::from(&self.data)
+    }
+
+    /// Get the history of the payment.
+    #[tracing::instrument(skip_all, fields(reference = self.reference().as_str()), err)]
+    pub async fn events(&self) -> Result<Vec<PaymentEvent>> {
+        let res = self.api.payment_events_inner(&self.reference).await?;
+
+        tracing::debug!("fetched payment events");
+
+        Ok(res)
+    }
+
     #[tracing::instrument(skip_all, fields(reference = self.reference().as_str()), err)]
     pub async fn cancel(&mut self) -> Result<()> {
-        let idempotency_key = self.api.create_unique_reference();
         let res = self
             .api
-            .0
-            .client
-            .post(format!(
-                "{}/epayment/v1/payments/{}/cancel",
-                self.api.0.base_url, self.reference.0
-            ))
-            .header("Idempotency-Key", &idempotency_key)
-            .bearer_auth(self.api.access_token().await?.token())
-            .header("Content-Length", 0)
-            .send()
-            .await?
-            .into_vipps_result()
-            .await?
-            .json::<AdjustmentRes>()
+            .adjust_payment(&self.reference, Adjustment::Cancel)
             .await?;
 
         tracing::debug!("canceled payment");
@@ -284,29 +511,11 @@ impl Payment {
         Ok(())
     }
 
-    #[cfg(not(feature = "mock"))]
     #[tracing::instrument(skip_all, fields(reference = self.reference().as_str()), err)]
     pub async fn capture(&mut self, amount: Amount) -> Result<()> {
-        // TODO: retry?
-        let idempotency_key = self.api.create_unique_reference();
         let res = self
             .api
-            .0
-            .client
-            .post(format!(
-                "{}/epayment/v1/payments/{}/capture",
-                self.api.0.base_url, self.reference.0
-            ))
-            .header("Idempotency-Key", &idempotency_key)
-            .bearer_auth(self.api.access_token().await?.token())
-            .json(&ModificationReq {
-                modification_amount: amount,
-            })
-            .send()
-            .await?
-            .into_vipps_result()
-            .await?
-            .json::<AdjustmentRes>()
+            .adjust_payment(&self.reference, Adjustment::Capture(amount))
             .await?;
 
         tracing::debug!("captured payment");
@@ -316,29 +525,11 @@ impl Payment {
         Ok(())
     }
 
-    #[cfg(not(feature = "mock"))]
     #[tracing::instrument(skip_all, fields(reference = self.reference().as_str()), err)]
     pub async fn refund(&mut self, amount: Amount) -> Result<()> {
-        // TODO: retry?
-        let idempotency_key = self.api.create_unique_reference();
         let res = self
             .api
-            .0
-            .client
-            .post(format!(
-                "{}/epayment/v1/payments/{}/refund",
-                self.api.0.base_url, self.reference.0
-            ))
-            .header("Idempotency-Key", &idempotency_key)
-            .bearer_auth(self.api.access_token().await?.token())
-            .json(&ModificationReq {
-                modification_amount: amount,
-            })
-            .send()
-            .await?
-            .into_vipps_result()
-            .await?
-            .json::<AdjustmentRes>()
+            .adjust_payment(&self.reference, Adjustment::Refund(amount))
             .await?;
 
         tracing::debug!("refunded payment");
@@ -368,31 +559,32 @@ struct ModificationReq {
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct GetPaymentRes {
-    amount: Amount,
-    state: PaymentState,
-    // aggregate: PayymentAggregate,
+    pub(crate) amount: Amount,
+    pub(crate) state: PaymentState,
+    pub(crate) aggregate: PaymentAggregate,
     payment_method: PaymentMethodResponse,
     profile: ProfileSub,
     // psp_reference: String,
     redirect_url: Option<String>,
-    reference: PaymentReference,
+    pub(crate) reference: PaymentReference,
 }
 
 impl GetPaymentRes {
     fn update(&mut self, adjustment: &AdjustmentRes) {
         self.amount = adjustment.amount.clone();
         self.state = adjustment.state.clone();
+        self.aggregate = adjustment.aggregate.clone();
     }
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct AdjustmentRes {
-    amount: Amount,
-    state: PaymentState,
-    // aggregate: PayymentAggregate,
-    psp_reference: String,
-    reference: PaymentReference,
+pub(crate) struct AdjustmentRes {
+    pub(crate) amount: Amount,
+    pub(crate) state: PaymentState,
+    pub(crate) aggregate: PaymentAggregate,
+    pub(crate) psp_reference: String,
+    pub(crate) reference: PaymentReference,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
@@ -409,7 +601,7 @@ struct ProfileSub {
     sub: Option<String>,
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum PaymentState {
     Created,
@@ -419,6 +611,52 @@ pub enum PaymentState {
     Terminated,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentAggregate {
+    pub authorized_amount: Amount,
+    pub cancelled_amount: Amount,
+    pub captured_amount: Amount,
+    pub refunded_amount: Amount,
+}
+
+impl PaymentAggregate {
+    pub(crate) fn empty(currency: Currency) -> Self {
+        Self {
+            authorized_amount: Amount::new(currency, 0),
+            cancelled_amount: Amount::new(currency, 0),
+            captured_amount: Amount::new(currency, 0),
+            refunded_amount: Amount::new(currency, 0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentEvent {
+    pub reference: PaymentReference,
+    pub psp_reference: String,
+    pub name: PaymentEventName,
+    pub amount: Amount,
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: time::OffsetDateTime,
+    pub idempotency_key: Option<String>,
+    pub success: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PaymentEventName {
+    Created,
+    Aborted,
+    Expired,
+    Cancelled,
+    Captured,
+    Refunded,
+    Authorized,
+    Terminated,
+}
+
 impl PaymentState {
     pub fn completed(&self) -> bool {
         !matches!(self, PaymentState::Created)
@@ -426,67 +664,24 @@ impl PaymentState {
 }
 
 #[cfg(feature = "mock")]
-pub(crate) mod mock {
+mod mock {
     use super::*;
 
-    use std::collections::HashMap;
-    use std::sync::{LazyLock, Mutex};
-
-    struct MockPaymentData {
-        pay_data: GetPaymentRes,
-        return_url: Option<String>,
-    }
-
-    struct MockPaymentDb {
-        pub db: Mutex<HashMap<PaymentReference, MockPaymentData>>,
-    }
-
-    static MOCK_DB: LazyLock<MockPaymentDb> = LazyLock::new(|| MockPaymentDb {
-        db: Mutex::new(HashMap::new()),
-    });
-
     impl<'a> CreatePaymentBuilder<'a> {
-        #[cfg(feature = "mock")]
         pub async fn send(self) -> Result<Payment> {
-            let redirect_url = Some(format!("/mock/vipps/payment/{}", self.req.reference.0));
-            let payment = Payment {
+            let data = self.api.inner.mock.create_payment(&self.req)?;
+
+            Ok(Payment {
                 api: self.api.clone(),
-                reference: self.req.reference.clone(),
-                data: GetPaymentRes {
-                    amount: self.req.amount,
-                    state: PaymentState::Created,
-                    payment_method: PaymentMethodResponse {
-                        ty: self.req.payment_method.ty,
-                        card_bin: None,
-                    },
-                    profile: ProfileSub { sub: None },
-                    redirect_url,
-                    reference: self.req.reference.clone(),
-                },
-            };
-
-            mock::MOCK_DB.db.lock().unwrap().insert(
-                payment.reference.clone(),
-                MockPaymentData {
-                    pay_data: payment.data.clone(),
-                    return_url: self.req.return_url,
-                },
-            );
-
-            Ok(payment)
+                reference: self.req.reference,
+                data,
+            })
         }
     }
 
     impl VippsApi {
         pub async fn payment(&self, reference: PaymentReference) -> Result<Payment> {
-            let data = mock::MOCK_DB
-                .db
-                .lock()
-                .unwrap()
-                .get(&reference)
-                .ok_or(Error::Mock)?
-                .pay_data
-                .clone();
+            let data = self.inner.mock.payment_data(&reference)?;
 
             Ok(Payment {
                 api: self.clone(),
@@ -494,41 +689,36 @@ pub(crate) mod mock {
                 data,
             })
         }
-    }
-
-    impl Payment {
-        pub fn set_mock_state(&self, state: PaymentState) {
-            mock::MOCK_DB
-                .db
-                .lock()
-                .unwrap()
-                .get_mut(&self.reference)
-                .unwrap()
-                .pay_data
-                .state = state;
-        }
 
-        pub fn get_mock_return_url(&self) -> Option<String> {
-            mock::MOCK_DB
-                .db
-                .lock()
-                .unwrap()
-                .get_mut(&self.reference)
-                .unwrap()
-                .return_url
-                .clone()
+        pub(super) async fn payment_events_inner(
+            &self,
+            reference: &PaymentReference,
+        ) -> Result<Vec<PaymentEvent>> {
+            self.inner
+                .mock
+                .payment_events(reference)
+                .ok_or_else(|| crate::mock::payment_not_found(reference))
         }
 
-        pub async fn cancel(&mut self) -> Result<()> {
-            Ok(())
+        pub(super) async fn adjust_payment(
+            &self,
+            reference: &PaymentReference,
+            adjustment: Adjustment,
+        ) -> Result<AdjustmentRes> {
+            self.inner.mock.adjust_payment(reference, adjustment)
         }
+    }
 
-        pub async fn capture(&mut self, _amount: Amount) -> Result<()> {
-            Ok(())
+    impl Payment {
+        /// See [`crate::mock::MockStore::set_payment_state`].
+        pub fn set_mock_state(&self, state: PaymentState) -> Result<()> {
+            self.api
+                .mock_store()
+                .set_payment_state(&self.reference, state)
         }
 
-        pub async fn refund(&mut self, _amount: Amount) -> Result<()> {
-            Ok(())
+        pub fn get_mock_return_url(&self) -> Option<String> {
+            self.api.mock_store().payment_return_url(&self.reference)
         }
     }
 }
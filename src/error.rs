@@ -4,15 +4,42 @@
 pub enum Error {
     #[error("http error")]
     HttpError(#[from] reqwest::Error),
+    #[error("io error")]
+    Io(#[from] std::io::Error),
     #[error("api error {code} ({title:?})")]
     ApiError {
         code: u16,
         title: String,
         detail: String,
     },
-    #[cfg(feature = "mock")]
-    #[error("mock error")]
-    Mock,
+    #[error("invalid access token response: {0}")]
+    InvalidAccessToken(String),
+    #[error("unknown merchant {0}")]
+    UnknownMerchant(String),
+    #[error("invalid merchant info: {0}")]
+    InvalidMerchant(String),
+    /// Vipps answered in a way the api does not describe.
+    #[error("unexpected response: {0}")]
+    UnexpectedResponse(String),
+    #[error("token store error: {0}")]
+    TokenStore(String),
+    #[error("invalid config: {0}")]
+    Config(String),
+    #[error("fixture error: {0}")]
+    Fixture(String),
+    #[error("invalid system info: {0}")]
+    InvalidSystemInfo(#[from] SystemInfoError),
+}
+
+/// A [`crate::SystemInfo`] field breaking the rules for system headers.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SystemInfoError {
+    #[error("{field} is empty")]
+    Empty { field: &'static str },
+    #[error("{field} is {len} characters, at most {max} are allowed", max = crate::MAX_SYSTEM_HEADER_LEN)]
+    TooLong { field: &'static str, len: usize },
+    #[error("{field} contains the invalid character {c:?}")]
+    InvalidCharacter { field: &'static str, c: char },
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -0,0 +1,590 @@
+//! Recording and replaying of http fixtures.
+//!
+//! A client built with [`VippsApiBuilder::record_fixtures`] makes real
+//! requests and writes every request and response to a json file. A client
+//! built with [`VippsApiBuilder::replay_fixtures`] answers requests from such
+//! a file without touching the network, so api code can be tested offline.
+//!
+//! Credentials are never written: the access token endpoint is not recorded,
+//! credential headers are dropped, and token fields and `token` query
+//! parameters of urls in bodies are scrubbed.
+//! When replaying, access tokens are made up on the spot.
+//!
+//! Requests are matched on method and path, each recorded response is served
+//! once in the order it was recorded. Since references appear in paths,
+//! tests replaying fixtures should use fixed payment references.
+
+use crate::*;
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use transport::Transport;
+
+const TOKEN_PATH: &str = "/accesstoken/get";
+const SCRUBBED: &str = "<scrubbed>";
+
+/// Headers that are never written to fixtures.
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "client_id",
+    "client_secret",
+    "ocp-apim-subscription-key",
+    "set-cookie",
+];
+
+/// Body fields that are scrubbed before writing fixtures.
+const SECRET_FIELDS: &[&str] = &[
+    "access_token",
+    "accessToken",
+    "token",
+    "client_secret",
+    "clientSecret",
+    "callbackAuthorizationToken",
+];
+
+/// Query parameters of urls in bodies that are scrubbed before writing
+/// fixtures, like the token in the landing page url of payments.
+const SECRET_QUERY_PARAMS: &[&str] = &["token"];
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+struct Fixtures {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct RecordedRequest {
+    method: String,
+    /// Path and query, without the base url.
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Option<serde_json::Value>,
+}
+
+fn request_path(url: &reqwest::Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    }
+}
+
+fn scrub_headers(headers: &reqwest::header::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter(|(name, value)| !value.is_sensitive() && !SECRET_HEADERS.contains(&name.as_str()))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Scrub secret query parameters from a url, `None` if there are none.
+fn scrub_url(text: &str) -> Option<String> {
+    let mut url = reqwest::Url::parse(text).ok()?;
+    let is_secret = |name: &str| SECRET_QUERY_PARAMS.contains(&name);
+    if !url.query_pairs().any(|(name, _)| is_secret(&name)) {
+        return None;
+    }
+
+    let pairs = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if is_secret(&name) {
+                SCRUBBED.into()
+            } else {
+                value
+            };
+            (name.into_owned(), value.into_owned())
+        })
+        .collect::<Vec<_>>();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+
+    Some(url.into())
+}
+
+fn scrub_json(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&key.as_str()) && value.is_string() {
+                    *value = serde_json::Value::String(SCRUBBED.to_string());
+                } else {
+                    scrub_json(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(scrub_json),
+        serde_json::Value::String(text) => {
+            if let Some(url) = scrub_url(text) {
+                *text = url;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Bodies are stored as json when possible to keep fixtures readable.
+fn record_body(body: &[u8]) -> Option<serde_json::Value> {
+    if body.is_empty() {
+        return None;
+    }
+
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(mut value) => {
+            scrub_json(&mut value);
+            Some(value)
+        }
+        Err(_) => Some(serde_json::Value::String(
+            String::from_utf8_lossy(body).into_owned(),
+        )),
+    }
+}
+
+fn replay_body(body: &Option<serde_json::Value>) -> Vec<u8> {
+    match body {
+        None => Vec::new(),
+        Some(serde_json::Value::String(body)) => body.clone().into_bytes(),
+        Some(body) => body.to_string().into_bytes(),
+    }
+}
+
+fn make_response(
+    status: u16,
+    headers: reqwest::header::HeaderMap,
+    body: Vec<u8>,
+) -> Result<reqwest::Response> {
+    let mut res = http::Response::new(body);
+    *res.status_mut() = reqwest::StatusCode::from_u16(status)
+        .map_err(|_| Error::Fixture(format!("invalid status {status}")))?;
+    *res.headers_mut() = headers;
+
+    Ok(reqwest::Response::from(res))
+}
+
+/// Writes requests sent through another transport to a fixture file.
+pub(crate) struct Recorder {
+    path: PathBuf,
+    transport: Arc<dyn Transport>,
+    fixtures: Mutex<Fixtures>,
+}
+
+impl Recorder {
+    pub(crate) fn new(path: PathBuf, transport: Arc<dyn Transport>) -> Self {
+        Self {
+            path,
+            transport,
+            fixtures: Mutex::new(Fixtures::default()),
+        }
+    }
+
+    async fn record(&self, req: reqwest::Request) -> Result<reqwest::Response> {
+        let path = request_path(req.url());
+        if path.ends_with(TOKEN_PATH) {
+            return self.transport.execute(req).await;
+        }
+
+        let request = RecordedRequest {
+            method: req.method().to_string(),
+            path,
+            headers: scrub_headers(req.headers()),
+            body: record_body(
+                req.body()
+                    .and_then(|body| body.as_bytes())
+                    .unwrap_or_default(),
+            ),
+        };
+
+        let res = self.transport.execute(req).await?;
+        let status = res.status().as_u16();
+        let headers = res.headers().clone();
+        let body = res.bytes().await?.to_vec();
+
+        let response = RecordedResponse {
+            status,
+            headers: scrub_headers(&headers),
+            body: record_body(&body),
+        };
+
+        self.write(Interaction { request, response })?;
+
+        make_response(status, headers, body)
+    }
+
+    /// The whole file is rewritten after every request, so fixtures are
+    /// complete even if the test panics.
+    fn write(&self, interaction: Interaction) -> Result<()> {
+        let mut fixtures = self.fixtures.lock().unwrap();
+        fixtures.interactions.push(interaction);
+
+        let data =
+            serde_json::to_vec_pretty(&*fixtures).map_err(|err| Error::Fixture(err.to_string()))?;
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+}
+
+impl Transport for Recorder {
+    fn execute(&self, req: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+        self.record(req).boxed()
+    }
+}
+
+/// Answers requests from a fixture file.
+pub(crate) struct Replayer {
+    interactions: Mutex<Vec<Option<Interaction>>>,
+}
+
+impl Replayer {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let fixtures = serde_json::from_slice::<Fixtures>(&data).map_err(|err| {
+            Error::Fixture(format!("invalid fixture file {}: {err}", path.display()))
+        })?;
+
+        Ok(Self {
+            interactions: Mutex::new(fixtures.interactions.into_iter().map(Some).collect()),
+        })
+    }
+
+    fn replay(&self, req: reqwest::Request) -> Result<reqwest::Response> {
+        let method = req.method().as_str();
+        let path = request_path(req.url());
+
+        if path.ends_with(TOKEN_PATH) {
+            return Self::token_response();
+        }
+
+        let mut interactions = self.interactions.lock().unwrap();
+        let interaction = interactions
+            .iter_mut()
+            .find(|interaction| {
+                interaction.as_ref().is_some_and(|interaction| {
+                    interaction.request.method == method && interaction.request.path == path
+                })
+            })
+            .and_then(Option::take)
+            .ok_or_else(|| Error::Fixture(format!("no recorded response for {method} {path}")))?;
+
+        let response = interaction.response;
+        let headers = response
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    reqwest::header::HeaderName::from_bytes(name.as_bytes()).ok()?,
+                    reqwest::header::HeaderValue::from_str(value).ok()?,
+                ))
+            })
+            .collect();
+
+        make_response(response.status, headers, replay_body(&response.body))
+    }
+
+    fn token_response() -> Result<reqwest::Response> {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let body = serde_json::json!({
+            "token_type": "Bearer",
+            "expires_in": "3600",
+            "ext_expires_in": "3600",
+            "expires_on": (now + 3600).to_string(),
+            "not_before": now.to_string(),
+            "resource": "replay",
+            "access_token": SCRUBBED,
+        });
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+
+        make_response(200, headers, body.to_string().into_bytes())
+    }
+}
+
+impl Transport for Replayer {
+    fn execute(&self, req: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+        futures::future::ready(self.replay(req)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    /// Answers every request with the same json body.
+    struct Canned(serde_json::Value);
+
+    impl Transport for Canned {
+        fn execute(&self, _req: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+            let res = make_response(200, HeaderMap::new(), self.0.to_string().into_bytes());
+            futures::future::ready(res).boxed()
+        }
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("vipps-fixtures-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn scrub_secret_headers() {
+        let mut sensitive = HeaderValue::from_static("signature");
+        sensitive.set_sensitive(true);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", HeaderValue::from_static("Bearer token"));
+        headers.insert("client_secret", HeaderValue::from_static("secret"));
+        headers.insert("Ocp-Apim-Subscription-Key", HeaderValue::from_static("key"));
+        headers.insert("X-Signature", sensitive);
+        headers.insert("Merchant-Serial-Number", HeaderValue::from_static("123456"));
+
+        assert_eq!(
+            scrub_headers(&headers),
+            [("merchant-serial-number".to_string(), "123456".to_string())]
+        );
+    }
+
+    #[test]
+    fn scrub_secret_fields() {
+        let mut value = serde_json::json!({
+            "reference": "order-1",
+            "callbackAuthorizationToken": "secret",
+            "sessions": [{ "token": "secret", "tokenType": "Bearer" }],
+            "amount": { "value": 1000 },
+            "redirectUrl": "https://landing.vipps.no/?token=eyJraWQiOiJqd3RrZXki&lang=no",
+            "returnUrl": "https://example.com/return?order=1",
+        });
+        scrub_json(&mut value);
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "reference": "order-1",
+                "callbackAuthorizationToken": SCRUBBED,
+                "sessions": [{ "token": SCRUBBED, "tokenType": "Bearer" }],
+                "amount": { "value": 1000 },
+                "redirectUrl": "https://landing.vipps.no/?token=%3Cscrubbed%3E&lang=no",
+                "returnUrl": "https://example.com/return?order=1",
+            })
+        );
+    }
+
+    #[test]
+    fn record_scrubs_credentials() {
+        let path = temp_path();
+        let recorder = Recorder::new(
+            path.clone(),
+            Arc::new(Canned(serde_json::json!({ "token": "session-token" }))),
+        );
+        let client = reqwest::Client::new();
+
+        let token_req = client
+            .post("https://apitest.vipps.no/accesstoken/get")
+            .header("client_secret", "secret")
+            .build()
+            .unwrap();
+        let session_req = client
+            .post("https://apitest.vipps.no/checkout/v3/session")
+            .header("client_secret", "secret")
+            .json(&serde_json::json!({
+                "merchantInfo": { "callbackAuthorizationToken": "secret" },
+            }))
+            .build()
+            .unwrap();
+
+        futures::executor::block_on(async {
+            recorder.execute(token_req).await.unwrap();
+            recorder.execute(session_req).await.unwrap();
+        });
+
+        let data = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!data.contains("secret"));
+        let fixtures = serde_json::from_str::<Fixtures>(&data).unwrap();
+        assert_eq!(fixtures.interactions.len(), 1);
+        assert_eq!(
+            fixtures.interactions[0].request.path,
+            "/checkout/v3/session"
+        );
+    }
+
+    fn fixture_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(format!("{name}.json"))
+    }
+
+    fn fixture_api(name: &str) -> VippsApi {
+        VippsApi::builder(
+            SystemInfo::new("acme-shop", "1.0.0").unwrap(),
+            MerchantInfo {
+                subscription_key: "subscription-key".into(),
+                msn: "123456".to_string(),
+            },
+            AuthInfo::Merchant {
+                client_id: "client-id".to_string(),
+                client_secret: "client-secret".into(),
+            },
+        )
+        .replay_fixtures(fixture_path(name))
+        .build()
+        .unwrap()
+    }
+
+    /// Every recorded response is served for its request, this runs with the
+    /// `mock` feature as well since it only uses the transport.
+    #[test]
+    fn replay_fixture_files() {
+        for name in ["epayment", "qr", "order-management"] {
+            let data = std::fs::read(fixture_path(name)).unwrap();
+            let fixtures = serde_json::from_slice::<Fixtures>(&data).unwrap();
+            let api = fixture_api(name);
+
+            futures::executor::block_on(async {
+                for interaction in fixtures.interactions {
+                    let method = interaction.request.method.parse().unwrap();
+                    let url = format!("{}{}", api.inner.base_url, interaction.request.path);
+                    let res = api
+                        .send_authorized(api.inner.client.request(method, url))
+                        .await
+                        .unwrap();
+
+                    assert_eq!(res.status().as_u16(), interaction.response.status);
+                    assert_eq!(
+                        res.bytes().await.unwrap(),
+                        replay_body(&interaction.response.body)
+                    );
+                }
+            });
+
+            // Secrets in bodies never make it to the files
+            let data = String::from_utf8(data).unwrap();
+            assert_eq!(
+                data.matches("token=").count(),
+                data.matches("token=%3Cscrubbed%3E").count(),
+                "{name} has an unscrubbed token"
+            );
+            assert!(!data.contains("client-secret"));
+        }
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[test]
+    fn replay_epayment() {
+        use epayment::{EpaymentApi, PaymentRequest, PaymentState};
+
+        let api = fixture_api("epayment");
+
+        let reference = "order-1".parse::<epayment::PaymentReference>().unwrap();
+        let mut request = PaymentRequest::new(Amount::nok(1000));
+        request.reference = Some(reference.clone());
+        request.return_url = Some("https://example.com/return".to_string());
+
+        futures::executor::block_on(async {
+            let created = api.initiate_payment(request).await.unwrap();
+            assert_eq!(created.reference, reference);
+
+            let mut payment = api.payment(reference.clone()).await.unwrap();
+            assert_eq!(payment.state(), PaymentState::Authorized);
+
+            payment.capture(Amount::nok(600)).await.unwrap();
+            assert_eq!(payment.aggregate().captured_amount, Amount::nok(600));
+
+            // Each recorded response is only served once
+            assert!(matches!(
+                api.payment(reference).await,
+                Err(Error::Fixture(_))
+            ));
+        });
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[test]
+    fn replay_qr() {
+        let api = fixture_api("qr");
+
+        futures::executor::block_on(async {
+            let qr = api
+                .create_redirect_qr_with_ttl("shop-door", "https://example.com/menu", 86400)
+                .await
+                .unwrap();
+            assert_eq!(qr.redirect_url(), "https://example.com/menu");
+            assert!(qr.expires_at().is_some());
+
+            let mut qr = api.get_redirect_qr("shop-door").await.unwrap().unwrap();
+            qr.update_redirect_url("https://example.com/lunch")
+                .await
+                .unwrap();
+            assert_eq!(qr.redirect_url(), "https://example.com/lunch");
+            qr.delete().await.unwrap();
+            assert!(api.get_redirect_qr("shop-door").await.unwrap().is_none());
+
+            let qr = api.create_callback_qr("till-1", "Till 1").await.unwrap();
+            assert_eq!(qr.location_description(), "Till 1");
+            assert_eq!(api.list_callback_qrs().await.unwrap().len(), 1);
+            qr.delete().await.unwrap();
+        });
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[test]
+    fn replay_order_management() {
+        use order_management::{OrderCategory, OrderLine, OrderManagementApi};
+
+        let api = fixture_api("order-management");
+        let order_line = OrderLine {
+            name: "Coffee".to_string(),
+            id: "coffee-1".to_string(),
+            total_amount: 1000,
+            total_amount_excluding_tax: 800,
+            total_tax_amount: 200,
+            tax_percentage: 25,
+            unit_info: None,
+            discount: None,
+            product_url: None,
+            is_return: None,
+            is_shipping: None,
+        };
+
+        futures::executor::block_on(async {
+            let reference = "order-1".parse().unwrap();
+            api.add_category(
+                &reference,
+                OrderCategory::Reciept,
+                "https://example.com/orders/1",
+            )
+            .await
+            .unwrap();
+            api.add_reciept(&reference, Currency::Nok, vec![order_line.clone()])
+                .await
+                .unwrap();
+
+            let unknown = "order-2".parse().unwrap();
+            assert!(matches!(
+                api.add_reciept(&unknown, Currency::Nok, vec![order_line])
+                    .await,
+                Err(Error::ApiError { code: 404, .. })
+            ));
+        });
+    }
+}
@@ -1,16 +1,37 @@
 mod accesstoken;
 mod basic;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod checkout;
+pub mod config;
 pub mod epayment;
 mod error;
+mod fixtures;
+pub mod listing;
+pub mod management;
+pub mod mock;
 pub mod order_management;
-mod qr;
+pub mod qr;
+pub mod reconcile;
+pub mod report;
+mod secret;
+pub mod token_store;
+pub mod transport;
 
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+pub use accesstoken::{AccessToken, DEFAULT_TOKEN_MARGIN, MAX_TOKEN_MARGIN};
 pub use basic::*;
+pub use config::{Environment, RetryPolicy, VippsConfig};
 pub use error::*;
+pub use secret::Secret;
+pub use token_store::TokenStore;
+pub use transport::Transport;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Deserialize)]
 pub struct SystemInfo {
     pub system_name: String,
     pub system_version: String,
@@ -18,101 +39,812 @@ pub struct SystemInfo {
     pub system_plugin_version: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+/// Longest value Vipps accepts in the system headers.
+pub const MAX_SYSTEM_HEADER_LEN: usize = 30;
+
+fn validate_system_field(field: &'static str, value: &str) -> Result<()> {
+    if value.is_empty() {
+        return Err(SystemInfoError::Empty { field }.into());
+    }
+    if value.len() > MAX_SYSTEM_HEADER_LEN {
+        return Err(SystemInfoError::TooLong {
+            field,
+            len: value.len(),
+        }
+        .into());
+    }
+    if let Some(c) = value.chars().find(|c| !c.is_ascii_graphic()) {
+        return Err(SystemInfoError::InvalidCharacter { field, c }.into());
+    }
+    Ok(())
+}
+
+impl SystemInfo {
+    pub fn new(system_name: impl Into<String>, system_version: impl Into<String>) -> Result<Self> {
+        let system_info = Self {
+            system_name: system_name.into(),
+            system_version: system_version.into(),
+            system_plugin_name: None,
+            system_plugin_version: None,
+        };
+        system_info.validate()?;
+        Ok(system_info)
+    }
+
+    pub fn with_plugin(
+        mut self,
+        plugin_name: impl Into<String>,
+        plugin_version: impl Into<String>,
+    ) -> Result<Self> {
+        self.system_plugin_name = Some(plugin_name.into());
+        self.system_plugin_version = Some(plugin_version.into());
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Check the fields against the rules Vipps has for the system headers,
+    /// each must be non-empty printable ascii without spaces and at most
+    /// [`MAX_SYSTEM_HEADER_LEN`] long.
+    pub fn validate(&self) -> Result<()> {
+        validate_system_field("system_name", &self.system_name)?;
+        validate_system_field("system_version", &self.system_version)?;
+        if let Some(plugin_name) = &self.system_plugin_name {
+            validate_system_field("system_plugin_name", plugin_name)?;
+        }
+        if let Some(plugin_version) = &self.system_plugin_version {
+            validate_system_field("system_plugin_version", plugin_version)?;
+        }
+        Ok(())
+    }
+
+    /// Headers identifying the system, sent with every request.
+    pub(crate) fn headers(&self) -> Result<reqwest::header::HeaderMap> {
+        self.validate()?;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+
+        // Validation ensures these are valid header values
+        headers.insert("Vipps-System-Name", (&self.system_name).try_into().unwrap());
+        headers.insert(
+            "Vipps-System-Version",
+            (&self.system_version).try_into().unwrap(),
+        );
+        if let Some(plugin_name) = &self.system_plugin_name {
+            headers.insert("Vipps-System-Plugin-Name", plugin_name.try_into().unwrap());
+        }
+        if let Some(plugin_version) = &self.system_plugin_version {
+            headers.insert(
+                "Vipps-System-Plugin-Version",
+                plugin_version.try_into().unwrap(),
+            );
+        }
+
+        Ok(headers)
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
 pub struct MerchantInfo {
-    pub subscription_key: String,
+    pub subscription_key: Secret,
     pub msn: String,
 }
 
+/// Merchant info and credentials are sent as headers, so they must be
+/// non-empty printable ascii.
+fn validate_merchant_field(field: &str, value: &str) -> Result<()> {
+    if value.is_empty() {
+        return Err(Error::InvalidMerchant(format!("{field} is empty")));
+    }
+    if let Some(c) = value.chars().find(|c| !c.is_ascii_graphic()) {
+        return Err(Error::InvalidMerchant(format!(
+            "{field} contains the invalid character {c:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Header value made from validated merchant info or credentials.
+pub(crate) fn merchant_header_value<T>(
+    field: &str,
+    value: T,
+) -> Result<reqwest::header::HeaderValue>
+where
+    T: TryInto<reqwest::header::HeaderValue>,
+{
+    value
+        .try_into()
+        .map_err(|_| Error::InvalidMerchant(format!("{field} is not a valid header value")))
+}
+
+impl MerchantInfo {
+    /// Check that the merchant serial number is a number and that the
+    /// subscription key can be sent as a header.
+    pub fn validate(&self) -> Result<()> {
+        validate_merchant_field("msn", &self.msn)?;
+        if !self.msn.chars().all(|c| c.is_ascii_digit()) {
+            return Err(Error::InvalidMerchant(format!(
+                "msn {:?} is not a number",
+                self.msn
+            )));
+        }
+        validate_merchant_field("subscription_key", self.subscription_key.expose())
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuthInfo {
+    /// Api keys belonging to a single merchant.
+    Merchant {
+        client_id: String,
+        client_secret: Secret,
+    },
+    /// Partner keys, these can be used for every merchant managed by the
+    /// partner. The partner's subscription key goes in [`MerchantInfo`].
+    Partner {
+        client_id: String,
+        client_secret: Secret,
+    },
+}
+
+impl AuthInfo {
+    pub fn client_id(&self) -> &str {
+        match self {
+            AuthInfo::Merchant { client_id, .. } | AuthInfo::Partner { client_id, .. } => client_id,
+        }
+    }
+
+    pub fn client_secret(&self) -> &Secret {
+        match self {
+            AuthInfo::Merchant { client_secret, .. } | AuthInfo::Partner { client_secret, .. } => {
+                client_secret
+            }
+        }
+    }
+
+    pub fn is_partner(&self) -> bool {
+        matches!(self, AuthInfo::Partner { .. })
+    }
+
+    /// Check that the credentials can be sent as headers.
+    pub fn validate(&self) -> Result<()> {
+        validate_merchant_field("client_id", self.client_id())?;
+        validate_merchant_field("client_secret", self.client_secret().expose())
+    }
+}
+
+/// A merchant sales unit the api can make calls for.
 #[derive(Clone, Debug)]
-pub struct AuthInfo {
-    pub client_id: String,
-    pub client_secret: String,
+pub(crate) struct Merchant {
+    merchant_info: MerchantInfo,
+    auth_info: AuthInfo,
+}
+
+enum FixtureMode {
+    Record(PathBuf),
+    Replay(PathBuf),
 }
 
 pub(crate) struct VippsApiData {
-    auth_info: AuthInfo,
+    merchants: HashMap<String, Arc<Merchant>>,
     client: reqwest::Client,
+    /// Headers describing the calling system, added to every request.
+    system_headers: reqwest::header::HeaderMap,
+    transport: Arc<dyn Transport>,
     base_url: String,
-    current_token: std::sync::RwLock<Option<accesstoken::AccessToken>>,
+    token_store: Arc<dyn TokenStore>,
+    /// One lock per client id, see [`VippsApi::access_token`].
+    refresh_locks: HashMap<String, Arc<tokio::sync::Mutex<()>>>,
+    token_margin: time::Duration,
+    retry_policy: RetryPolicy,
+    #[cfg(feature = "mock")]
+    mock: mock::MockStore,
 }
 
+/// Client for the Vipps api.
+///
+/// A client can hold credentials for several merchants. Calls are made for
+/// the merchant the client was created with unless another one is chosen
+/// with [`VippsApi::merchant`]. Clients using partner keys can make calls for
+/// any merchant managed by the partner.
 #[derive(Clone)]
-pub struct VippsApi(Arc<VippsApiData>);
+pub struct VippsApi {
+    inner: Arc<VippsApiData>,
+    merchant: Arc<Merchant>,
+}
+
+pub struct VippsApiBuilder {
+    system_info: SystemInfo,
+    default_msn: String,
+    merchants: HashMap<String, Arc<Merchant>>,
+    base_url: String,
+    token_store: Option<Arc<dyn TokenStore>>,
+    token_margin: time::Duration,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    transport: Option<Arc<dyn Transport>>,
+    fixtures: Option<FixtureMode>,
+    #[cfg(feature = "mock")]
+    mock: Option<mock::MockStore>,
+}
+
+impl VippsApiBuilder {
+    /// Build the client, fails if the [`SystemInfo`], any merchant or the
+    /// token margin is invalid.
+    pub fn build(self) -> Result<VippsApi> {
+        let system_headers = self.system_info.headers()?;
+        for merchant in self.merchants.values() {
+            merchant.merchant_info.validate()?;
+            merchant.auth_info.validate()?;
+        }
+        if self.token_margin.is_negative() || self.token_margin > MAX_TOKEN_MARGIN {
+            return Err(Error::Config(format!(
+                "token margin {} is not between 0s and {MAX_TOKEN_MARGIN}",
+                self.token_margin
+            )));
+        }
+
+        let mut client = reqwest::Client::builder();
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            client = client.connect_timeout(connect_timeout);
+        }
+        let client = client.build()?;
+
+        let transport = self
+            .transport
+            .unwrap_or_else(|| Arc::new(transport::ReqwestTransport::new(client.clone())));
+        let transport: Arc<dyn Transport> = match self.fixtures {
+            None => transport,
+            Some(FixtureMode::Record(path)) => Arc::new(fixtures::Recorder::new(path, transport)),
+            Some(FixtureMode::Replay(path)) => Arc::new(fixtures::Replayer::load(&path)?),
+        };
+
+        let refresh_locks = self
+            .merchants
+            .values()
+            .map(|merchant| {
+                (
+                    merchant.auth_info.client_id().to_string(),
+                    Arc::new(tokio::sync::Mutex::new(())),
+                )
+            })
+            .collect();
+
+        let merchant = self.merchants[&self.default_msn].clone();
+
+        Ok(VippsApi {
+            inner: Arc::new(VippsApiData {
+                merchants: self.merchants,
+                client,
+                system_headers,
+                transport,
+                base_url: self.base_url,
+                token_store: self
+                    .token_store
+                    .unwrap_or_else(|| Arc::new(token_store::MemoryTokenStore::new())),
+                refresh_locks,
+                token_margin: self.token_margin,
+                retry_policy: self.retry_policy,
+                #[cfg(feature = "mock")]
+                mock: self.mock.unwrap_or_default(),
+            }),
+            merchant,
+        })
+    }
+
+    /// Register another merchant, see [`VippsApi::merchant`].
+    pub fn set_merchant(&mut self, merchant_info: MerchantInfo, auth_info: AuthInfo) {
+        self.merchants.insert(
+            merchant_info.msn.clone(),
+            Arc::new(Merchant {
+                merchant_info,
+                auth_info,
+            }),
+        );
+    }
+
+    pub fn merchant(mut self, merchant_info: MerchantInfo, auth_info: AuthInfo) -> Self {
+        self.set_merchant(merchant_info, auth_info);
+        self
+    }
+
+    /// Environment to make calls against, defaults to [`Environment::Test`].
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.base_url = environment.base_url().to_string();
+    }
+
+    pub fn environment(mut self, environment: Environment) -> Self {
+        self.set_environment(environment);
+        self
+    }
+
+    /// Use the production environment instead of the test environment.
+    pub fn set_production(&mut self) {
+        self.set_environment(Environment::Production);
+    }
+
+    pub fn production(mut self) -> Self {
+        self.set_production();
+        self
+    }
+
+    pub fn set_token_store(&mut self, token_store: impl TokenStore + 'static) {
+        self.token_store = Some(Arc::new(token_store));
+    }
+
+    pub fn token_store(mut self, token_store: impl TokenStore + 'static) -> Self {
+        self.set_token_store(token_store);
+        self
+    }
+
+    /// Treat access tokens as expired when they expire within `margin`.
+    ///
+    /// Tokens are refreshed in the background when they expire within twice
+    /// the margin. Defaults to [`DEFAULT_TOKEN_MARGIN`], margins above
+    /// [`MAX_TOKEN_MARGIN`] are rejected by [`VippsApiBuilder::build`].
+    pub fn set_token_margin(&mut self, margin: time::Duration) {
+        self.token_margin = margin;
+    }
+
+    pub fn token_margin(mut self, margin: time::Duration) -> Self {
+        self.set_token_margin(margin);
+        self
+    }
+
+    /// Timeout for whole requests, by default there is none.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    pub fn set_connect_timeout(&mut self, timeout: Duration) {
+        self.connect_timeout = Some(timeout);
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.set_connect_timeout(timeout);
+        self
+    }
+
+    /// Retry failed requests, by default requests are not retried.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.set_retry_policy(retry_policy);
+        self
+    }
+
+    /// Send requests through `transport` instead of the default
+    /// [`transport::ReqwestTransport`].
+    ///
+    /// The timeouts set on the builder only apply to the default transport.
+    pub fn set_transport(&mut self, transport: impl Transport + 'static) {
+        self.transport = Some(Arc::new(transport));
+    }
+
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.set_transport(transport);
+        self
+    }
+
+    /// Make real requests and write them to a fixture file at `path`, see
+    /// [`VippsApiBuilder::replay_fixtures`].
+    pub fn set_record_fixtures(&mut self, path: impl Into<PathBuf>) {
+        self.fixtures = Some(FixtureMode::Record(path.into()));
+    }
+
+    pub fn record_fixtures(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_record_fixtures(path);
+        self
+    }
+
+    /// Answer requests from a fixture file recorded with
+    /// [`VippsApiBuilder::record_fixtures`] instead of making real requests.
+    ///
+    /// Credentials are scrubbed from recorded fixtures, and each recorded
+    /// response is replayed once for a request with the same method and path.
+    pub fn set_replay_fixtures(&mut self, path: impl Into<PathBuf>) {
+        self.fixtures = Some(FixtureMode::Replay(path.into()));
+    }
+
+    pub fn replay_fixtures(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_replay_fixtures(path);
+        self
+    }
+
+    /// Use an existing mock store, by default every client gets a new one.
+    #[cfg(feature = "mock")]
+    pub fn set_mock_store(&mut self, store: mock::MockStore) {
+        self.mock = Some(store);
+    }
+
+    #[cfg(feature = "mock")]
+    pub fn mock_store(mut self, store: mock::MockStore) -> Self {
+        self.set_mock_store(store);
+        self
+    }
+}
 
 impl VippsApi {
-    fn new_inner(
+    pub fn builder(
         system_info: SystemInfo,
         merchant_info: MerchantInfo,
         auth_info: AuthInfo,
-        base_url: String,
-    ) -> Self {
-        let mut default_headers = reqwest::header::HeaderMap::new();
+    ) -> VippsApiBuilder {
+        let mut builder = VippsApiBuilder {
+            system_info,
+            default_msn: merchant_info.msn.clone(),
+            merchants: HashMap::new(),
+            base_url: Environment::default().base_url().to_string(),
+            token_store: None,
+            token_margin: accesstoken::DEFAULT_TOKEN_MARGIN,
+            timeout: None,
+            connect_timeout: None,
+            retry_policy: RetryPolicy::default(),
+            transport: None,
+            fixtures: None,
+            #[cfg(feature = "mock")]
+            mock: None,
+        };
+        builder.set_merchant(merchant_info, auth_info);
+        builder
+    }
 
-        default_headers.insert(
+    pub fn new(
+        system_info: SystemInfo,
+        merchant_info: MerchantInfo,
+        auth_info: AuthInfo,
+    ) -> Result<Self> {
+        Self::builder(system_info, merchant_info, auth_info).build()
+    }
+
+    pub fn new_production(
+        system_info: SystemInfo,
+        merchant_info: MerchantInfo,
+        auth_info: AuthInfo,
+    ) -> Result<Self> {
+        Self::builder(system_info, merchant_info, auth_info)
+            .production()
+            .build()
+    }
+
+    /// Get a client making calls for the merchant with the given merchant
+    /// serial number.
+    ///
+    /// The merchant must have been registered with [`VippsApiBuilder::merchant`],
+    /// unless the current merchant uses [`AuthInfo::Partner`] keys. Then any
+    /// merchant not registered is reached with the same partner keys.
+    pub fn merchant(&self, msn: &str) -> Result<VippsApi> {
+        if let Some(merchant) = self.inner.merchants.get(msn) {
+            return Ok(VippsApi {
+                inner: self.inner.clone(),
+                merchant: merchant.clone(),
+            });
+        }
+
+        if !self.merchant.auth_info.is_partner() {
+            return Err(Error::UnknownMerchant(msn.to_string()));
+        }
+
+        let merchant_info = MerchantInfo {
+            subscription_key: self.merchant.merchant_info.subscription_key.clone(),
+            msn: msn.to_string(),
+        };
+        merchant_info.validate()?;
+
+        Ok(VippsApi {
+            inner: self.inner.clone(),
+            merchant: Arc::new(Merchant {
+                merchant_info,
+                auth_info: self.merchant.auth_info.clone(),
+            }),
+        })
+    }
+
+    /// Merchant serial number of the merchant calls are made for.
+    pub fn msn(&self) -> &str {
+        &self.merchant.merchant_info.msn
+    }
+
+    /// Tokens expiring within this margin are treated as expired, see
+    /// [`VippsApiBuilder::token_margin`].
+    pub fn token_margin(&self) -> time::Duration {
+        self.inner.token_margin
+    }
+
+    /// Merchant serial numbers of all registered merchants.
+    pub fn merchants(&self) -> impl Iterator<Item = &str> {
+        self.inner.merchants.keys().map(String::as_str)
+    }
+
+    pub(crate) fn current_merchant(&self) -> &Merchant {
+        &self.merchant
+    }
+
+    pub(crate) fn merchant_headers(&self) -> Result<reqwest::header::HeaderMap> {
+        let merchant_info = &self.current_merchant().merchant_info;
+        let mut headers = reqwest::header::HeaderMap::new();
+
+        headers.insert(
             "Ocp-Apim-Subscription-Key",
-            (&merchant_info.subscription_key).try_into().unwrap(),
+            merchant_header_value("subscription_key", &merchant_info.subscription_key)?,
         );
-        default_headers.insert(
+        headers.insert(
             "Merchant-Serial-Number",
-            (&merchant_info.msn).try_into().unwrap(),
+            merchant_header_value("msn", &merchant_info.msn)?,
         );
 
-        default_headers.insert(
-            "Vipps-System-Name",
-            (&system_info.system_name).try_into().unwrap(),
-        );
-        default_headers.insert(
-            "Vipps-System-Version",
-            (&system_info.system_version).try_into().unwrap(),
-        );
+        Ok(headers)
+    }
 
-        if let Some(plugin_name) = system_info.system_plugin_name.as_ref() {
-            default_headers.insert("Vipps-System-Plugin-Name", plugin_name.try_into().unwrap());
+    /// Send a request with the configured transport.
+    pub(crate) async fn execute(&self, req: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        self.execute_request(req.build()?).await
+    }
+
+    async fn execute_request(&self, mut req: reqwest::Request) -> Result<reqwest::Response> {
+        for (name, value) in &self.inner.system_headers {
+            req.headers_mut().insert(name, value.clone());
         }
-        if let Some(plugin_version) = system_info.system_plugin_name.as_ref() {
-            default_headers.insert(
-                "Vipps-System-Plugin-Version",
-                plugin_version.try_into().unwrap(),
-            );
+
+        self.inner.transport.execute(req).await
+    }
+
+    /// Send a request, retrying it according to the configured
+    /// [`RetryPolicy`]. Requests with streaming bodies are sent once.
+    pub(crate) async fn send_retrying(
+        &self,
+        req: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let req = req.build()?;
+        let policy = &self.inner.retry_policy;
+        if !policy.can_retry(&req) {
+            return self.execute_request(req).await;
         }
 
-        let client = reqwest::Client::builder()
-            .default_headers(default_headers)
-            .build()
-            .unwrap();
+        let mut attempt = 0;
+        loop {
+            let retry_req = match req.try_clone() {
+                Some(retry_req) if attempt < policy.max_retries => retry_req,
+                _ => return self.execute_request(req).await,
+            };
 
-        Self(Arc::new(VippsApiData {
-            auth_info,
-            client,
-            base_url,
-            current_token: std::sync::RwLock::new(None),
-        }))
+            let delay = match self.execute_request(retry_req).await {
+                Ok(res) if policy.should_retry_status(res.status()) => {
+                    tracing::debug!(status = %res.status(), attempt, "retrying request");
+                    policy.retry_delay(attempt, Some(&res))
+                }
+                Err(Error::HttpError(err)) if policy.should_retry_error(&err) => {
+                    tracing::debug!(error = %err, attempt, "retrying request");
+                    policy.retry_delay(attempt, None)
+                }
+                res => return res,
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
     }
 
-    pub fn new(system_info: SystemInfo, merchant_info: MerchantInfo, auth_info: AuthInfo) -> Self {
-        Self::new_inner(
-            system_info,
-            merchant_info,
-            auth_info,
-            "https://apitest.vipps.no".to_string(),
+    fn create_unique_reference(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system_info() -> SystemInfo {
+        SystemInfo::new("acme-shop", "1.2.3")
+            .unwrap()
+            .with_plugin("acme-vipps", "4.5.6")
+            .unwrap()
+    }
+
+    fn api() -> VippsApi {
+        VippsApi::new(
+            system_info(),
+            MerchantInfo {
+                subscription_key: "subscription-key".into(),
+                msn: "123456".to_string(),
+            },
+            AuthInfo::Merchant {
+                client_id: "client-id".to_string(),
+                client_secret: "client-secret".into(),
+            },
         )
+        .unwrap()
     }
 
-    pub fn new_production(
-        system_info: SystemInfo,
-        merchant_info: MerchantInfo,
-        auth_info: AuthInfo,
-    ) -> Self {
-        Self::new_inner(
+    fn header_list(headers: &reqwest::header::HeaderMap) -> Vec<(&str, &str)> {
+        let mut headers = headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_str().unwrap()))
+            .collect::<Vec<_>>();
+        headers.sort();
+        headers
+    }
+
+    #[test]
+    fn system_headers() {
+        let headers = system_info().headers().unwrap();
+
+        assert_eq!(
+            header_list(&headers),
+            [
+                ("vipps-system-name", "acme-shop"),
+                ("vipps-system-plugin-name", "acme-vipps"),
+                ("vipps-system-plugin-version", "4.5.6"),
+                ("vipps-system-version", "1.2.3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn system_headers_without_plugin() {
+        let headers = SystemInfo::new("acme-shop", "1.2.3")
+            .unwrap()
+            .headers()
+            .unwrap();
+
+        assert_eq!(
+            header_list(&headers),
+            [
+                ("vipps-system-name", "acme-shop"),
+                ("vipps-system-version", "1.2.3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn merchant_headers() {
+        let api = api();
+        let headers = api.merchant_headers().unwrap();
+
+        assert_eq!(
+            header_list(&headers),
+            [
+                ("merchant-serial-number", "123456"),
+                ("ocp-apim-subscription-key", "subscription-key"),
+            ]
+        );
+        assert!(headers["ocp-apim-subscription-key"].is_sensitive());
+    }
+
+    #[test]
+    fn invalid_system_info() {
+        assert!(matches!(
+            SystemInfo::new("", "1.0.0"),
+            Err(Error::InvalidSystemInfo(SystemInfoError::Empty {
+                field: "system_name"
+            }))
+        ));
+        assert!(matches!(
+            SystemInfo::new("acme", "x".repeat(MAX_SYSTEM_HEADER_LEN + 1)),
+            Err(Error::InvalidSystemInfo(SystemInfoError::TooLong {
+                field: "system_version",
+                len: 31
+            }))
+        ));
+        assert!(matches!(
+            SystemInfo::new("acme", "1.0.0")
+                .unwrap()
+                .with_plugin("acme\nplugin", "1.0.0"),
+            Err(Error::InvalidSystemInfo(
+                SystemInfoError::InvalidCharacter {
+                    field: "system_plugin_name",
+                    c: '\n'
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn build_rejects_invalid_system_info() {
+        let system_info = SystemInfo {
+            system_name: "acme shop".to_string(),
+            system_version: "1.0.0".to_string(),
+            system_plugin_name: None,
+            system_plugin_version: None,
+        };
+        let res = VippsApi::new(
             system_info,
-            merchant_info,
-            auth_info,
-            "https://api.vipps.no".to_string(),
+            MerchantInfo {
+                subscription_key: "subscription-key".into(),
+                msn: "123456".to_string(),
+            },
+            AuthInfo::Merchant {
+                client_id: "client-id".to_string(),
+                client_secret: "client-secret".into(),
+            },
+        );
+
+        assert!(matches!(
+            res,
+            Err(Error::InvalidSystemInfo(
+                SystemInfoError::InvalidCharacter { c: ' ', .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn invalid_merchant() {
+        let api = VippsApi::new(
+            system_info(),
+            MerchantInfo {
+                subscription_key: "subscription-key".into(),
+                msn: "123456".to_string(),
+            },
+            AuthInfo::Partner {
+                client_id: "client-id".to_string(),
+                client_secret: "client-secret".into(),
+            },
         )
+        .unwrap();
+
+        assert_eq!(api.merchant("456").unwrap().msn(), "456");
+        assert!(matches!(
+            api.merchant("456\n"),
+            Err(Error::InvalidMerchant(_))
+        ));
+        assert!(matches!(api.merchant(""), Err(Error::InvalidMerchant(_))));
     }
 
-    fn create_unique_reference(&self) -> String {
-        uuid::Uuid::new_v4().to_string()
+    #[test]
+    fn build_rejects_invalid_merchant() {
+        let res = VippsApi::new(
+            system_info(),
+            MerchantInfo {
+                subscription_key: "subscription-key\n".into(),
+                msn: "123456".to_string(),
+            },
+            AuthInfo::Merchant {
+                client_id: "client-id".to_string(),
+                client_secret: "client-secret".into(),
+            },
+        );
+
+        assert!(matches!(res, Err(Error::InvalidMerchant(_))));
+    }
+
+    #[test]
+    fn build_rejects_invalid_token_margin() {
+        let build = |margin| {
+            VippsApi::builder(
+                system_info(),
+                MerchantInfo {
+                    subscription_key: "subscription-key".into(),
+                    msn: "123456".to_string(),
+                },
+                AuthInfo::Merchant {
+                    client_id: "client-id".to_string(),
+                    client_secret: "client-secret".into(),
+                },
+            )
+            .token_margin(margin)
+            .build()
+        };
+
+        assert!(build(time::Duration::ZERO).is_ok());
+        assert!(build(MAX_TOKEN_MARGIN).is_ok());
+        assert!(matches!(
+            build(time::Duration::hours(1)),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            build(time::Duration::minutes(-1)),
+            Err(Error::Config(_))
+        ));
     }
 }
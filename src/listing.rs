@@ -0,0 +1,142 @@
+//! Listing of payments.
+//!
+//! Vipps has no endpoint for listing payments, so payments are found through
+//! the funds transactions in the Report api. This means only payments that
+//! have been captured or refunded are found. Payments that never got that
+//! far, like payments stuck in [`PaymentState::Created`], can be included by
+//! passing their references to [`ListPaymentsBuilder::references`].
+
+use crate::*;
+
+use std::collections::HashSet;
+
+use epayment::{Payment, PaymentReference, PaymentState};
+use futures::{Stream, StreamExt, TryStreamExt};
+
+/// # Listing payments
+impl VippsApi {
+    /// List payments with transactions on the ledgers of the current
+    /// merchant.
+    pub fn list_payments(&self) -> ListPaymentsBuilder<'_> {
+        let today = time::OffsetDateTime::now_utc().date();
+
+        ListPaymentsBuilder {
+            api: self,
+            from: today,
+            to: today,
+            states: Vec::new(),
+            references: Vec::new(),
+        }
+    }
+}
+
+pub struct ListPaymentsBuilder<'a> {
+    api: &'a VippsApi,
+    from: time::Date,
+    to: time::Date,
+    states: Vec<PaymentState>,
+    references: Vec<PaymentReference>,
+}
+
+impl<'a> ListPaymentsBuilder<'a> {
+    /// Stream the payments, each payment is yielded once.
+    pub fn stream(self) -> impl Stream<Item = Result<Payment>> + 'a {
+        let api = self.api;
+        let ledger_references = self.ledger_references();
+        let states = self.states;
+        let mut seen = HashSet::new();
+
+        let extra_references = futures::stream::iter(self.references.into_iter().map(Ok));
+
+        ledger_references
+            .chain(extra_references)
+            .try_filter(move |reference| futures::future::ready(seen.insert(reference.clone())))
+            .and_then(move |reference| api.payment(reference))
+            .try_filter(move |payment| {
+                futures::future::ready(states.is_empty() || states.contains(&payment.state()))
+            })
+    }
+
+    /// References of all payments with funds transactions in the date range.
+    ///
+    /// Ledgers can settle for several sales units, so transactions for other
+    /// merchants are skipped.
+    fn ledger_references(&self) -> impl Stream<Item = Result<PaymentReference>> + 'a {
+        let api = self.api;
+        let dates = date_range(self.from, self.to);
+
+        futures::stream::once(api.list_ledgers())
+            .map_ok(|ledgers| futures::stream::iter(ledgers.into_iter().map(Ok::<_, Error>)))
+            .try_flatten()
+            .map_ok(move |ledger| {
+                futures::stream::iter(dates.clone())
+                    .map(move |date| api.funds_transactions(&ledger.ledger_id, date))
+                    .flatten()
+            })
+            .try_flatten()
+            .try_filter_map(move |transaction| {
+                let for_merchant = transaction
+                    .recipient_handle
+                    .as_deref()
+                    .is_none_or(|handle| is_recipient_handle_for(handle, api.msn()));
+                futures::future::ok(transaction.reference.filter(|_| for_merchant))
+            })
+    }
+
+    /// Only look at ledger dates from `from` to `to`, inclusive. Defaults to
+    /// today.
+    pub fn set_ledger_dates(&mut self, from: time::Date, to: time::Date) {
+        self.from = from;
+        self.to = to;
+    }
+
+    pub fn ledger_dates(mut self, from: time::Date, to: time::Date) -> Self {
+        self.set_ledger_dates(from, to);
+        self
+    }
+
+    /// Only yield payments in the given state, can be given several times.
+    pub fn set_state(&mut self, state: PaymentState) {
+        self.states.push(state);
+    }
+
+    pub fn state(mut self, state: PaymentState) -> Self {
+        self.set_state(state);
+        self
+    }
+
+    /// Also look at these payments, for payments not yet on any ledger.
+    pub fn set_references(&mut self, references: impl IntoIterator<Item = PaymentReference>) {
+        self.references.extend(references);
+    }
+
+    pub fn references(mut self, references: impl IntoIterator<Item = PaymentReference>) -> Self {
+        self.set_references(references);
+        self
+    }
+}
+
+/// Recipient handles are the merchant serial number prefixed by country, like
+/// `NO:123456`.
+fn is_recipient_handle_for(handle: &str, msn: &str) -> bool {
+    handle.rsplit(':').next() == Some(msn)
+}
+
+fn date_range(from: time::Date, to: time::Date) -> Vec<time::Date> {
+    std::iter::successors(Some(from), |date| date.next_day())
+        .take_while(|date| *date <= to)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipient_handle() {
+        assert!(is_recipient_handle_for("NO:123456", "123456"));
+        assert!(is_recipient_handle_for("123456", "123456"));
+        assert!(!is_recipient_handle_for("NO:654321", "123456"));
+        assert!(!is_recipient_handle_for("NO:1234567", "123456"));
+    }
+}
@@ -0,0 +1,398 @@
+//! Management api, for looking up merchants, sales units and product orders.
+//!
+//! See <https://developer.vippsmobilepay.com/docs/APIs/management-api/> for
+//! the api documentation from Vipps.
+
+use crate::*;
+
+/// # Management api
+impl VippsApi {
+    /// Look up a merchant by business identifier, see
+    /// [`BusinessIdentifier::orgno`].
+    #[tracing::instrument(skip(self), err)]
+    pub async fn get_merchant_details(
+        &self,
+        business_identifier: &BusinessIdentifier,
+    ) -> Result<Option<MerchantDetails>> {
+        self.management_find(&business_identifier.merchant_path())
+            .await
+    }
+
+    /// List merchants available to the partner.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn list_merchants(&self, page: PageQuery) -> Result<Page<MerchantSummary>> {
+        let res = self
+            .management_get::<MerchantsPage>("merchants", &page.query())
+            .await?;
+
+        Ok(Page {
+            items: res.merchants,
+            page: page.page,
+            page_size: page.page_size,
+        })
+    }
+
+    /// List the sales units of a merchant.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn list_sales_units(
+        &self,
+        business_identifier: &BusinessIdentifier,
+        page: PageQuery,
+    ) -> Result<Page<SalesUnitSummary>> {
+        let res = self
+            .management_get::<SalesUnitsPage>(
+                &format!("{}/sales-units", business_identifier.merchant_path()),
+                &page.query(),
+            )
+            .await?;
+
+        Ok(Page {
+            items: res.sales_units,
+            page: page.page,
+            page_size: page.page_size,
+        })
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    pub async fn get_sales_unit(&self, msn: &str) -> Result<Option<SalesUnit>> {
+        self.management_find(&format!("sales-units/{}", msn)).await
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    pub async fn get_product_order(&self, product_order_id: &str) -> Result<Option<ProductOrder>> {
+        self.management_find(&format!("products/orders/{}", product_order_id))
+            .await
+    }
+
+    /// List the product orders made for a merchant.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn list_product_orders(
+        &self,
+        business_identifier: &BusinessIdentifier,
+        page: PageQuery,
+    ) -> Result<Page<ProductOrder>> {
+        let res = self
+            .management_get::<ProductOrdersPage>(
+                &format!("{}/product-orders", business_identifier.merchant_path()),
+                &page.query(),
+            )
+            .await?;
+
+        Ok(Page {
+            items: res.product_orders,
+            page: page.page,
+            page_size: page.page_size,
+        })
+    }
+
+    async fn management_request(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<reqwest::Response> {
+        self.send_authorized(
+            self.inner
+                .client
+                .get(format!("{}/management/v1/{}", self.inner.base_url, path))
+                .query(query),
+        )
+        .await
+    }
+
+    /// Get a management api resource, a missing resource is an error.
+    async fn management_get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        let data = self
+            .management_request(path, query)
+            .await?
+            .into_vipps_result()
+            .await?
+            .json::<T>()
+            .await?;
+
+        tracing::debug!(path, "got management api resource");
+
+        Ok(data)
+    }
+
+    /// Get a single management api resource, returning `None` if it does not
+    /// exist.
+    async fn management_find<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<Option<T>> {
+        let res = self.management_request(path, &[]).await?;
+
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let data = res.into_vipps_result().await?.json::<T>().await?;
+
+        tracing::debug!(path, "got management api resource");
+
+        Ok(Some(data))
+    }
+}
+
+/// Which page to fetch from a paginated endpoint. Pages start at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageQuery {
+    pub page: u32,
+    pub page_size: u32,
+}
+
+impl Default for PageQuery {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            page_size: 100,
+        }
+    }
+}
+
+impl PageQuery {
+    pub fn new(page: u32, page_size: u32) -> Self {
+        Self { page, page_size }
+    }
+
+    fn query(&self) -> [(&'static str, String); 2] {
+        [
+            ("page", self.page.to_string()),
+            ("pageSize", self.page_size.to_string()),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: u32,
+    pub page_size: u32,
+}
+
+impl<T> Page<T> {
+    /// Query for the next page, or `None` if this was the last page.
+    pub fn next(&self) -> Option<PageQuery> {
+        (self.items.len() >= self.page_size as usize)
+            .then(|| PageQuery::new(self.page + 1, self.page_size))
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MerchantsPage {
+    #[serde(default)]
+    merchants: Vec<MerchantSummary>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SalesUnitsPage {
+    #[serde(default)]
+    sales_units: Vec<SalesUnitSummary>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProductOrdersPage {
+    #[serde(default)]
+    product_orders: Vec<ProductOrder>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerchantSummary {
+    pub business_identifier: BusinessIdentifier,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerchantDetails {
+    pub business_identifier: BusinessIdentifier,
+    pub name: String,
+    pub address: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Address {
+    #[serde(default)]
+    pub address_lines: Vec<String>,
+    pub city: Option<String>,
+    pub postal_code: Option<String>,
+    pub country_code: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SalesUnitSummary {
+    pub msn: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SalesUnit {
+    pub msn: String,
+    pub name: String,
+    pub business_identifier: BusinessIdentifier,
+    #[serde(default)]
+    pub product_types: Vec<String>,
+    pub configuration: Option<SalesUnitConfiguration>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BusinessIdentifier {
+    pub scheme: String,
+    pub id: String,
+}
+
+/// Scheme of Norwegian organization numbers.
+pub const ORGNO_SCHEME: &str = "business:NO:ORG";
+
+impl BusinessIdentifier {
+    pub fn new(scheme: impl Into<String>, id: impl Into<String>) -> Self {
+        Self {
+            scheme: scheme.into(),
+            id: id.into(),
+        }
+    }
+
+    /// A Norwegian organization number.
+    pub fn orgno(orgno: impl Into<String>) -> Self {
+        Self::new(ORGNO_SCHEME, orgno)
+    }
+
+    fn merchant_path(&self) -> String {
+        format!("merchants/{}/{}", self.scheme, self.id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SalesUnitConfiguration {
+    pub capture_type: Option<String>,
+    pub skip_landing_page: Option<bool>,
+    pub recurring: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductOrder {
+    pub product_order_id: String,
+    pub business_identifier: Option<BusinessIdentifier>,
+    pub sales_unit_name: Option<String>,
+    pub product_type: Option<String>,
+    pub status: Option<String>,
+    pub msn: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deserialize a sample payload and check it serializes back unchanged.
+    fn round_trip<T>(json: serde_json::Value) -> T
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let value = serde_json::from_value::<T>(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&value).unwrap(), json);
+        value
+    }
+
+    #[test]
+    fn merchant_details() {
+        let merchant = round_trip::<MerchantDetails>(serde_json::json!({
+            "businessIdentifier": { "scheme": "business:NO:ORG", "id": "987654321" },
+            "name": "Acme Shop AS",
+            "address": {
+                "addressLines": ["Robert Levins gate 5"],
+                "city": "Oslo",
+                "postalCode": "0154",
+                "countryCode": "NO",
+            },
+        }));
+
+        assert_eq!(
+            merchant.business_identifier,
+            BusinessIdentifier::orgno("987654321")
+        );
+    }
+
+    #[test]
+    fn sales_unit() {
+        let sales_unit = round_trip::<SalesUnit>(serde_json::json!({
+            "msn": "123456",
+            "name": "Acme Shop",
+            "businessIdentifier": { "scheme": "business:NO:ORG", "id": "987654321" },
+            "productTypes": ["VIPPS_CHECKOUT", "ECOMMERCE_PAYMENTS"],
+            "configuration": {
+                "captureType": "ReserveCapture",
+                "skipLandingPage": false,
+                "recurring": null,
+            },
+        }));
+
+        assert_eq!(sales_unit.product_types.len(), 2);
+    }
+
+    #[test]
+    fn pages() {
+        let merchants = serde_json::from_value::<MerchantsPage>(serde_json::json!({
+            "merchants": [{ "businessIdentifier": { "scheme": "business:NO:ORG", "id": "987654321" } }],
+        }))
+        .unwrap();
+        assert_eq!(
+            merchants.merchants,
+            [MerchantSummary {
+                business_identifier: BusinessIdentifier::orgno("987654321")
+            }]
+        );
+
+        let sales_units = serde_json::from_value::<SalesUnitsPage>(serde_json::json!({
+            "salesUnits": [{ "msn": "123456" }, { "msn": "654321" }],
+        }))
+        .unwrap();
+        assert_eq!(sales_units.sales_units.len(), 2);
+
+        let order = serde_json::json!({
+            "productOrderId": "81b83246-5c19-4b3e-a2b5-25ee8d2d4b76",
+            "businessIdentifier": { "scheme": "business:NO:ORG", "id": "987654321" },
+            "salesUnitName": "Acme Shop",
+            "productType": "VIPPS_CHECKOUT",
+            "status": "IN_PROGRESS",
+            "msn": null,
+        });
+        let orders = serde_json::from_value::<ProductOrdersPage>(serde_json::json!({
+            "productOrders": [order.clone()],
+        }))
+        .unwrap();
+        assert_eq!(orders.product_orders, [round_trip::<ProductOrder>(order)]);
+
+        // Empty pages may leave out the list
+        let empty = serde_json::from_value::<MerchantsPage>(serde_json::json!({})).unwrap();
+        assert!(empty.merchants.is_empty());
+    }
+
+    #[test]
+    fn next_page() {
+        let page = Page {
+            items: vec![1, 2],
+            page: 1,
+            page_size: 2,
+        };
+        assert_eq!(page.next(), Some(PageQuery::new(2, 2)));
+
+        let last = Page {
+            items: vec![3],
+            page: 2,
+            page_size: 2,
+        };
+        assert_eq!(last.next(), None);
+    }
+}
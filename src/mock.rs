@@ -0,0 +1,1065 @@
+//! In-memory backend standing in for the Vipps api.
+//!
+//! The api traits [`epayment::EpaymentApi`], [`qr::QrApi`] and
+//! [`order_management::OrderManagementApi`] are implemented by [`VippsApi`]
+//! and by [`MockVippsApi`], so code can be generic over the backend. Such
+//! code can be tested against [`MockVippsApi`] without any feature flags.
+//!
+//! With the `mock` feature [`VippsApi`] itself is backed by a [`MockStore`]
+//! instead of the Vipps api. Every client built gets its own store, so tests
+//! running in parallel do not see each other's payments. Clients that should
+//! share state can be given the same store with `VippsApiBuilder::mock_store`.
+//!
+//! Mock payments redirect to a landing page standing in for the Vipps app,
+//! see [`MockStore::handle_request`] for serving it.
+
+use crate::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use order_management::{OrderCategory, OrderLine, OrderManagementApi};
+use qr::{CallbackQrData, QrApi, QrData};
+
+use epayment::{
+    Adjustment, AdjustmentRes, CreatePaymentReq, EpaymentApi, GetPaymentRes, PaymentDetails,
+    PaymentEvent, PaymentEventName, PaymentReference, PaymentRequest, PaymentState,
+};
+
+struct MockPayment {
+    data: GetPaymentRes,
+    return_url: Option<String>,
+    events: Vec<PaymentEvent>,
+}
+
+impl MockPayment {
+    fn push_event(&mut self, name: PaymentEventName, amount: Amount) {
+        self.events.push(PaymentEvent {
+            reference: self.data.reference.clone(),
+            psp_reference: uuid::Uuid::new_v4().to_string(),
+            name,
+            amount,
+            timestamp: time::OffsetDateTime::now_utc(),
+            idempotency_key: None,
+            success: true,
+        });
+    }
+
+    /// Amounts must be positive and in the currency of the payment.
+    fn check_amount(&self, amount: &Amount) -> Result<()> {
+        if amount.currency() != self.data.amount.currency() {
+            return Err(bad_request(format!(
+                "Currency {:?} does not match the payment currency {:?}",
+                amount.currency(),
+                self.data.amount.currency()
+            )));
+        }
+        if amount.value() <= 0 {
+            return Err(bad_request("Amount must be positive".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Move the payment to a new state like Vipps would, authorizing sets the
+    /// authorized amount.
+    fn set_state(&mut self, state: PaymentState) {
+        if self.data.state == state {
+            return;
+        }
+
+        let no_amount = Amount::new(self.data.amount.currency(), 0);
+        match state {
+            PaymentState::Created => {}
+            PaymentState::Authorized => {
+                self.data.aggregate.authorized_amount = self.data.amount.clone();
+                self.push_event(PaymentEventName::Authorized, self.data.amount.clone());
+            }
+            PaymentState::Aborted => self.push_event(PaymentEventName::Aborted, no_amount),
+            PaymentState::Expired => self.push_event(PaymentEventName::Expired, no_amount),
+            PaymentState::Terminated => self.push_event(PaymentEventName::Terminated, no_amount),
+        }
+        self.data.state = state;
+    }
+
+    /// Cancel what has not been captured, only authorized payments with
+    /// nothing captured or created payments can be cancelled.
+    fn cancel(&mut self) -> Result<()> {
+        let aggregate = &mut self.data.aggregate;
+        match self.data.state {
+            PaymentState::Created => {}
+            PaymentState::Authorized if aggregate.captured_amount.value() == 0 => {}
+            PaymentState::Authorized => {
+                return Err(bad_request(
+                    "Captured payments can not be cancelled".to_string(),
+                ))
+            }
+            ref state => {
+                return Err(bad_request(format!(
+                    "Payments in state {state:?} can not be cancelled"
+                )))
+            }
+        }
+
+        let cancelled = Amount::new(
+            aggregate.authorized_amount.currency(),
+            aggregate.authorized_amount.value() - aggregate.captured_amount.value(),
+        );
+        aggregate.cancelled_amount = cancelled.clone();
+        self.data.state = PaymentState::Terminated;
+        self.push_event(PaymentEventName::Cancelled, cancelled);
+        Ok(())
+    }
+
+    /// Capture part of the authorized amount.
+    fn capture(&mut self, amount: Amount) -> Result<()> {
+        self.check_amount(&amount)?;
+        if self.data.state != PaymentState::Authorized {
+            return Err(bad_request(format!(
+                "Payments in state {:?} can not be captured",
+                self.data.state
+            )));
+        }
+
+        let aggregate = &mut self.data.aggregate;
+        let capturable = aggregate.authorized_amount.value()
+            - aggregate.captured_amount.value()
+            - aggregate.cancelled_amount.value();
+        if amount.value() > capturable {
+            return Err(bad_request(format!(
+                "Can not capture {} when only {capturable} is left to capture",
+                amount.value()
+            )));
+        }
+
+        aggregate.captured_amount = Amount::new(
+            amount.currency(),
+            aggregate.captured_amount.value() + amount.value(),
+        );
+        self.push_event(PaymentEventName::Captured, amount);
+        Ok(())
+    }
+
+    /// Refund part of the captured amount.
+    fn refund(&mut self, amount: Amount) -> Result<()> {
+        self.check_amount(&amount)?;
+
+        let aggregate = &mut self.data.aggregate;
+        let refundable = aggregate.captured_amount.value() - aggregate.refunded_amount.value();
+        if amount.value() > refundable {
+            return Err(bad_request(format!(
+                "Can not refund {} when only {refundable} is left to refund",
+                amount.value()
+            )));
+        }
+
+        aggregate.refunded_amount = Amount::new(
+            amount.currency(),
+            aggregate.refunded_amount.value() + amount.value(),
+        );
+        self.push_event(PaymentEventName::Refunded, amount);
+        Ok(())
+    }
+}
+
+/// Order management data added to a payment.
+#[derive(Clone, Debug, Default)]
+pub struct MockOrder {
+    pub category: Option<OrderCategory>,
+    pub order_details_url: Option<String>,
+    pub receipt: Option<MockReceipt>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MockReceipt {
+    pub order_lines: Vec<OrderLine>,
+    pub currency: Currency,
+}
+
+#[derive(Default)]
+struct MockData {
+    payments: HashMap<PaymentReference, MockPayment>,
+    /// Qrs by merchant serial number and id.
+    redirect_qrs: BTreeMap<(String, String), QrData>,
+    callback_qrs: BTreeMap<(String, String), CallbackQrData>,
+    orders: HashMap<PaymentReference, MockOrder>,
+}
+
+impl MockData {
+    fn payment(&self, reference: &PaymentReference) -> Result<&MockPayment> {
+        self.payments
+            .get(reference)
+            .ok_or_else(|| payment_not_found(reference))
+    }
+
+    fn payment_mut(&mut self, reference: &PaymentReference) -> Result<&mut MockPayment> {
+        self.payments
+            .get_mut(reference)
+            .ok_or_else(|| payment_not_found(reference))
+    }
+}
+
+/// Path the redirect urls of mock payments start with.
+pub const MOCK_PAYMENT_PATH: &str = "/mock/vipps/payment/";
+
+/// The error Vipps responds with for operations not allowed on a resource.
+fn bad_request(detail: String) -> Error {
+    Error::ApiError {
+        code: 400,
+        title: "Bad Request".to_string(),
+        detail,
+    }
+}
+
+/// The error Vipps responds with when creating a resource that exists.
+fn conflict(detail: String) -> Error {
+    Error::ApiError {
+        code: 409,
+        title: "Conflict".to_string(),
+        detail,
+    }
+}
+
+/// The error Vipps responds with for unknown resources.
+fn not_found(detail: String) -> Error {
+    Error::ApiError {
+        code: 404,
+        title: "Not Found".to_string(),
+        detail,
+    }
+}
+
+pub(crate) fn payment_not_found(reference: &PaymentReference) -> Error {
+    not_found(format!("Payment {reference} was not found"))
+}
+
+/// State of the mock backend, cloning gives a handle to the same state.
+#[derive(Clone, Default)]
+pub struct MockStore {
+    data: Arc<Mutex<MockData>>,
+}
+
+impl MockStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockData> {
+        self.data.lock().unwrap()
+    }
+
+    pub(crate) fn create_payment(&self, req: &CreatePaymentReq) -> Result<GetPaymentRes> {
+        let mut data = self.lock();
+        if data.payments.contains_key(&req.reference) {
+            return Err(conflict(format!(
+                "Payment {} already exists",
+                req.reference
+            )));
+        }
+
+        let redirect_url = format!("{MOCK_PAYMENT_PATH}{}", req.reference);
+        let mut payment = MockPayment {
+            data: req.created_payment(Some(redirect_url)),
+            return_url: req.return_url.clone(),
+            events: Vec::new(),
+        };
+        payment.push_event(PaymentEventName::Created, req.amount.clone());
+
+        let created = payment.data.clone();
+        data.payments.insert(req.reference.clone(), payment);
+        Ok(created)
+    }
+
+    pub(crate) fn payment_data(&self, reference: &PaymentReference) -> Result<GetPaymentRes> {
+        Ok(self.lock().payment(reference)?.data.clone())
+    }
+
+    pub(crate) fn adjust_payment(
+        &self,
+        reference: &PaymentReference,
+        adjustment: Adjustment,
+    ) -> Result<AdjustmentRes> {
+        let mut data = self.lock();
+        let payment = data.payment_mut(reference)?;
+
+        match adjustment {
+            Adjustment::Cancel => payment.cancel()?,
+            Adjustment::Capture(amount) => payment.capture(amount)?,
+            Adjustment::Refund(amount) => payment.refund(amount)?,
+        }
+
+        Ok(AdjustmentRes {
+            amount: payment.data.amount.clone(),
+            state: payment.data.state.clone(),
+            aggregate: payment.data.aggregate.clone(),
+            psp_reference: payment.events.last().unwrap().psp_reference.clone(),
+            reference: reference.clone(),
+        })
+    }
+
+    pub(crate) fn create_redirect_qr(
+        &self,
+        msn: &str,
+        id: &str,
+        uri: &str,
+        ttl: Option<u32>,
+    ) -> Result<QrData> {
+        let key = (msn.to_string(), id.to_string());
+        let mut data = self.lock();
+        if data.redirect_qrs.contains_key(&key) {
+            return Err(conflict(format!("Redirect qr {id} already exists")));
+        }
+
+        let qr = QrData {
+            id: id.to_string(),
+            url: format!("/mock/vipps/qr/{msn}/{id}"),
+            redirect_url: uri.to_string(),
+            expires_at: ttl
+                .map(|ttl| time::OffsetDateTime::now_utc() + time::Duration::seconds(ttl.into())),
+        };
+        data.redirect_qrs.insert(key, qr.clone());
+
+        Ok(qr)
+    }
+
+    pub(crate) fn redirect_qr(&self, msn: &str, id: &str) -> Option<QrData> {
+        let key = (msn.to_string(), id.to_string());
+        self.lock().redirect_qrs.get(&key).cloned()
+    }
+
+    pub(crate) fn merchant_redirect_qrs(&self, msn: &str) -> Vec<QrData> {
+        self.lock()
+            .redirect_qrs
+            .iter()
+            .filter(|((qr_msn, _), _)| qr_msn == msn)
+            .map(|(_, qr)| qr.clone())
+            .collect()
+    }
+
+    pub(crate) fn update_redirect_qr(&self, msn: &str, id: &str, url: &str) -> Result<QrData> {
+        let key = (msn.to_string(), id.to_string());
+        let mut data = self.lock();
+        let qr = data
+            .redirect_qrs
+            .get_mut(&key)
+            .ok_or_else(|| not_found(format!("Redirect qr {id} was not found")))?;
+
+        qr.redirect_url = url.to_string();
+        Ok(qr.clone())
+    }
+
+    pub(crate) fn delete_redirect_qr(&self, msn: &str, id: &str) -> Result<()> {
+        let key = (msn.to_string(), id.to_string());
+        self.lock()
+            .redirect_qrs
+            .remove(&key)
+            .ok_or_else(|| not_found(format!("Redirect qr {id} was not found")))?;
+        Ok(())
+    }
+
+    /// Create or update a callback qr.
+    pub(crate) fn create_callback_qr(
+        &self,
+        msn: &str,
+        id: &str,
+        location_description: &str,
+    ) -> CallbackQrData {
+        let qr = CallbackQrData {
+            merchant_serial_number: msn.to_string(),
+            merchant_qr_id: id.to_string(),
+            location_description: location_description.to_string(),
+            qr_image_url: format!("/mock/vipps/callback-qr/{msn}/{id}"),
+            qr_content: format!("mock-callback-qr:{msn}:{id}"),
+        };
+        self.lock()
+            .callback_qrs
+            .insert((msn.to_string(), id.to_string()), qr.clone());
+        qr
+    }
+
+    pub(crate) fn callback_qr(&self, msn: &str, id: &str) -> Option<CallbackQrData> {
+        let key = (msn.to_string(), id.to_string());
+        self.lock().callback_qrs.get(&key).cloned()
+    }
+
+    pub(crate) fn merchant_callback_qrs(&self, msn: &str) -> Vec<CallbackQrData> {
+        self.lock()
+            .callback_qrs
+            .iter()
+            .filter(|((qr_msn, _), _)| qr_msn == msn)
+            .map(|(_, qr)| qr.clone())
+            .collect()
+    }
+
+    pub(crate) fn delete_callback_qr(&self, msn: &str, id: &str) -> Result<()> {
+        let key = (msn.to_string(), id.to_string());
+        self.lock()
+            .callback_qrs
+            .remove(&key)
+            .ok_or_else(|| not_found(format!("Callback qr {id} was not found")))?;
+        Ok(())
+    }
+
+    pub(crate) fn add_order_category(
+        &self,
+        reference: &PaymentReference,
+        category: OrderCategory,
+        order_details_url: &str,
+    ) -> Result<()> {
+        let mut data = self.lock();
+        data.payment(reference)?;
+
+        let order = data.orders.entry(reference.clone()).or_default();
+        order.category = Some(category);
+        order.order_details_url = Some(order_details_url.to_string());
+        Ok(())
+    }
+
+    pub(crate) fn add_order_receipt(
+        &self,
+        reference: &PaymentReference,
+        receipt: MockReceipt,
+    ) -> Result<()> {
+        let mut data = self.lock();
+        data.payment(reference)?;
+
+        data.orders.entry(reference.clone()).or_default().receipt = Some(receipt);
+        Ok(())
+    }
+
+    /// Remove everything from the store.
+    pub fn reset(&self) {
+        *self.lock() = MockData::default();
+    }
+
+    /// References of all payments created.
+    pub fn payments(&self) -> Vec<PaymentReference> {
+        self.lock().payments.keys().cloned().collect()
+    }
+
+    pub fn payment_state(&self, reference: &PaymentReference) -> Option<PaymentState> {
+        let data = self.lock();
+        Some(data.payments.get(reference)?.data.state.clone())
+    }
+
+    /// The return url the payment was created with.
+    pub fn payment_return_url(&self, reference: &PaymentReference) -> Option<String> {
+        self.lock().payments.get(reference)?.return_url.clone()
+    }
+
+    pub fn payment_events(&self, reference: &PaymentReference) -> Option<Vec<PaymentEvent>> {
+        Some(self.lock().payments.get(reference)?.events.clone())
+    }
+
+    /// Change the state of a payment, for example to simulate the customer
+    /// approving it. Authorizing a payment authorizes its full amount.
+    pub fn set_payment_state(
+        &self,
+        reference: &PaymentReference,
+        state: PaymentState,
+    ) -> Result<()> {
+        self.lock().payment_mut(reference)?.set_state(state);
+        Ok(())
+    }
+
+    /// Redirect qrs of all merchants.
+    pub fn redirect_qrs(&self) -> Vec<QrData> {
+        self.lock().redirect_qrs.values().cloned().collect()
+    }
+
+    /// Callback qrs of all merchants.
+    pub fn callback_qrs(&self) -> Vec<CallbackQrData> {
+        self.lock().callback_qrs.values().cloned().collect()
+    }
+
+    /// Category and receipt added to a payment with the order management api.
+    pub fn order(&self, reference: &PaymentReference) -> Option<MockOrder> {
+        self.lock().orders.get(reference).cloned()
+    }
+}
+
+impl std::fmt::Debug for MockStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data = self.lock();
+        f.debug_struct("MockStore")
+            .field("payments", &data.payments.len())
+            .field("redirect_qrs", &data.redirect_qrs.len())
+            .field("callback_qrs", &data.callback_qrs.len())
+            .field("orders", &data.orders.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "mock")]
+impl VippsApi {
+    /// The mock backend of this client.
+    pub fn mock_store(&self) -> &MockStore {
+        &self.inner.mock
+    }
+}
+
+/// A mock backend for a single merchant, implementing [`EpaymentApi`],
+/// [`QrApi`] and [`OrderManagementApi`] in memory.
+#[derive(Clone, Debug)]
+pub struct MockVippsApi {
+    store: MockStore,
+    msn: String,
+}
+
+impl MockVippsApi {
+    pub fn new(msn: impl Into<String>) -> Self {
+        Self::with_store(MockStore::new(), msn)
+    }
+
+    /// Use an existing store, for example one shared with other mocks.
+    pub fn with_store(store: MockStore, msn: impl Into<String>) -> Self {
+        Self {
+            store,
+            msn: msn.into(),
+        }
+    }
+
+    pub fn store(&self) -> &MockStore {
+        &self.store
+    }
+
+    pub fn msn(&self) -> &str {
+        &self.msn
+    }
+}
+
+impl EpaymentApi for MockVippsApi {
+    async fn initiate_payment(&self, request: PaymentRequest) -> Result<PaymentDetails> {
+        let reference = request
+            .reference
+            .clone()
+            .unwrap_or_else(|| PaymentReference(uuid::Uuid::new_v4().to_string()));
+        let data = self.store.create_payment(&request.into_req(reference))?;
+        Ok(PaymentDetails::from(&data))
+    }
+
+    async fn get_payment(&self, reference: &PaymentReference) -> Result<PaymentDetails> {
+        Ok(PaymentDetails::from(&self.store.payment_data(reference)?))
+    }
+
+    async fn payment_events(&self, reference: &PaymentReference) -> Result<Vec<PaymentEvent>> {
+        self.store
+            .payment_events(reference)
+            .ok_or_else(|| payment_not_found(reference))
+    }
+
+    async fn cancel_payment(&self, reference: &PaymentReference) -> Result<()> {
+        self.store.adjust_payment(reference, Adjustment::Cancel)?;
+        Ok(())
+    }
+
+    async fn capture_payment(&self, reference: &PaymentReference, amount: Amount) -> Result<()> {
+        self.store
+            .adjust_payment(reference, Adjustment::Capture(amount))?;
+        Ok(())
+    }
+
+    async fn refund_payment(&self, reference: &PaymentReference, amount: Amount) -> Result<()> {
+        self.store
+            .adjust_payment(reference, Adjustment::Refund(amount))?;
+        Ok(())
+    }
+}
+
+impl QrApi for MockVippsApi {
+    async fn add_redirect_qr(
+        &self,
+        id: &str,
+        redirect_url: &str,
+        ttl: Option<u32>,
+    ) -> Result<QrData> {
+        self.store
+            .create_redirect_qr(&self.msn, id, redirect_url, ttl)
+    }
+
+    async fn find_redirect_qr(&self, id: &str) -> Result<Option<QrData>> {
+        Ok(self.store.redirect_qr(&self.msn, id))
+    }
+
+    async fn redirect_qrs(&self) -> Result<Vec<QrData>> {
+        Ok(self.store.merchant_redirect_qrs(&self.msn))
+    }
+
+    async fn update_redirect_qr(&self, id: &str, redirect_url: &str) -> Result<QrData> {
+        self.store.update_redirect_qr(&self.msn, id, redirect_url)
+    }
+
+    async fn delete_redirect_qr(&self, id: &str) -> Result<()> {
+        self.store.delete_redirect_qr(&self.msn, id)
+    }
+
+    async fn set_callback_qr(
+        &self,
+        id: &str,
+        location_description: &str,
+    ) -> Result<CallbackQrData> {
+        Ok(self
+            .store
+            .create_callback_qr(&self.msn, id, location_description))
+    }
+
+    async fn find_callback_qr(&self, id: &str) -> Result<Option<CallbackQrData>> {
+        Ok(self.store.callback_qr(&self.msn, id))
+    }
+
+    async fn callback_qrs(&self) -> Result<Vec<CallbackQrData>> {
+        Ok(self.store.merchant_callback_qrs(&self.msn))
+    }
+
+    async fn delete_callback_qr(&self, id: &str) -> Result<()> {
+        self.store.delete_callback_qr(&self.msn, id)
+    }
+}
+
+impl OrderManagementApi for MockVippsApi {
+    async fn add_category(
+        &self,
+        reference: &PaymentReference,
+        category: OrderCategory,
+        details_url: &str,
+    ) -> Result<()> {
+        self.store
+            .add_order_category(reference, category, details_url)
+    }
+
+    async fn add_reciept(
+        &self,
+        reference: &PaymentReference,
+        currency: Currency,
+        order_lines: Vec<OrderLine>,
+    ) -> Result<()> {
+        self.store.add_order_receipt(
+            reference,
+            MockReceipt {
+                order_lines,
+                currency,
+            },
+        )
+    }
+}
+
+/// A request to the mock landing page, see [`MockStore::handle_request`].
+#[derive(Clone, Copy, Debug)]
+pub struct MockRequest<'a> {
+    pub method: &'a str,
+    /// Path without the query string.
+    pub path: &'a str,
+    /// Url encoded form body of `POST` requests.
+    pub body: &'a [u8],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    fn html(status: u16, body: String) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", "text/html; charset=utf-8".to_string())],
+            body,
+        }
+    }
+
+    fn redirect(location: String) -> Self {
+        Self {
+            status: 303,
+            headers: vec![("Location", location)],
+            body: String::new(),
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn form_action(body: &[u8]) -> Option<&str> {
+    std::str::from_utf8(body)
+        .ok()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("action="))
+}
+
+fn landing_page(reference: &PaymentReference, payment: &MockPayment) -> String {
+    let reference = escape_html(reference.as_str());
+    let amount = &payment.data.amount;
+    let state = &payment.data.state;
+
+    let actions = if *state == PaymentState::Created {
+        format!(
+            r#"<form method="post" action="{MOCK_PAYMENT_PATH}{reference}">
+<button name="action" value="approve">Approve</button>
+<button name="action" value="reject">Reject</button>
+<button name="action" value="expire">Let expire</button>
+</form>"#
+        )
+    } else {
+        "<p>The payment has been handled.</p>".to_string()
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Mock Vipps payment</title></head>
+<body>
+<h1>Mock Vipps payment</h1>
+<p>Reference: {reference}</p>
+<p>Amount: {} {:?}</p>
+<p>State: {state:?}</p>
+{actions}
+</body>
+</html>
+"#,
+        amount.value(),
+        amount.currency(),
+    )
+}
+
+impl MockStore {
+    /// Serve the landing page mock payments redirect to.
+    ///
+    /// The page lets the customer approve, reject or let the payment expire,
+    /// after which they are redirected to the return url of the payment.
+    /// This is not tied to any web framework, route requests for paths
+    /// starting with [`MOCK_PAYMENT_PATH`] here. Returns `None` for other
+    /// paths.
+    pub fn handle_request(&self, req: MockRequest<'_>) -> Option<MockResponse> {
+        let reference = req.path.strip_prefix(MOCK_PAYMENT_PATH)?;
+        let reference = PaymentReference(reference.trim_end_matches('/').to_string());
+
+        let mut data = self.lock();
+        let Some(payment) = data.payments.get_mut(&reference) else {
+            return Some(MockResponse::html(
+                404,
+                "<p>Payment not found</p>".to_string(),
+            ));
+        };
+
+        match req.method {
+            "GET" | "HEAD" => Some(MockResponse::html(200, landing_page(&reference, payment))),
+            "POST" => {
+                let state = match form_action(req.body) {
+                    Some("approve") => PaymentState::Authorized,
+                    Some("reject") => PaymentState::Aborted,
+                    Some("expire") => PaymentState::Expired,
+                    _ => return Some(MockResponse::html(400, "<p>Unknown action</p>".to_string())),
+                };
+                if payment.data.state != PaymentState::Created {
+                    return Some(MockResponse::html(
+                        409,
+                        "<p>The payment has already been handled</p>".to_string(),
+                    ));
+                }
+
+                payment.set_state(state);
+                tracing::debug!(reference = reference.as_str(), state = ?payment.data.state, "mock payment handled");
+
+                let location = payment
+                    .return_url
+                    .clone()
+                    .unwrap_or_else(|| format!("{MOCK_PAYMENT_PATH}{}", reference.as_str()));
+                Some(MockResponse::redirect(location))
+            }
+            _ => Some(MockResponse::html(
+                405,
+                "<p>Method not allowed</p>".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(state: PaymentState) -> MockPayment {
+        let req = PaymentRequest::new(Amount::nok(1000))
+            .into_req(PaymentReference("order-1".to_string()));
+        let mut payment = MockPayment {
+            data: req.created_payment(None),
+            return_url: None,
+            events: Vec::new(),
+        };
+        payment.set_state(PaymentState::Authorized);
+        payment.set_state(state);
+        payment
+    }
+
+    fn is_bad_request<T>(res: Result<T>) -> bool {
+        matches!(res, Err(Error::ApiError { code: 400, .. }))
+    }
+
+    fn is_not_found<T>(res: Result<T>) -> bool {
+        matches!(res, Err(Error::ApiError { code: 404, .. }))
+    }
+
+    fn reference(name: &str) -> PaymentReference {
+        PaymentReference(name.to_string())
+    }
+
+    fn create_payment(store: &MockStore, name: &str) -> Result<GetPaymentRes> {
+        store.create_payment(&PaymentRequest::new(Amount::nok(1000)).into_req(reference(name)))
+    }
+
+    #[test]
+    fn partial_captures() {
+        let mut payment = payment(PaymentState::Authorized);
+
+        payment.capture(Amount::nok(300)).unwrap();
+        payment.capture(Amount::nok(700)).unwrap();
+
+        assert_eq!(payment.data.aggregate.captured_amount, Amount::nok(1000));
+        assert!(is_bad_request(payment.capture(Amount::nok(1))));
+    }
+
+    #[test]
+    fn capture_over_authorized() {
+        let mut payment = payment(PaymentState::Authorized);
+
+        assert!(is_bad_request(payment.capture(Amount::nok(1001))));
+        assert_eq!(payment.data.aggregate.captured_amount, Amount::nok(0));
+    }
+
+    #[test]
+    fn capture_invalid_amount() {
+        let mut payment = payment(PaymentState::Authorized);
+
+        assert!(is_bad_request(payment.capture(Amount::nok(0))));
+        assert!(is_bad_request(
+            payment.capture(Amount::new(Currency::Eur, 100))
+        ));
+    }
+
+    #[test]
+    fn refund_over_captured() {
+        let mut payment = payment(PaymentState::Authorized);
+        payment.capture(Amount::nok(500)).unwrap();
+
+        payment.refund(Amount::nok(200)).unwrap();
+        assert!(is_bad_request(payment.refund(Amount::nok(301))));
+        payment.refund(Amount::nok(300)).unwrap();
+
+        assert_eq!(payment.data.aggregate.refunded_amount, Amount::nok(500));
+    }
+
+    #[test]
+    fn capture_after_abort_or_cancel() {
+        let mut aborted = payment(PaymentState::Aborted);
+        assert!(is_bad_request(aborted.capture(Amount::nok(100))));
+
+        let mut cancelled = payment(PaymentState::Authorized);
+        cancelled.cancel().unwrap();
+        assert_eq!(cancelled.data.aggregate.cancelled_amount, Amount::nok(1000));
+        assert!(is_bad_request(cancelled.capture(Amount::nok(100))));
+    }
+
+    #[test]
+    fn cancel_after_capture() {
+        let mut payment = payment(PaymentState::Authorized);
+        payment.capture(Amount::nok(100)).unwrap();
+
+        assert!(is_bad_request(payment.cancel()));
+        assert_eq!(payment.data.state, PaymentState::Authorized);
+    }
+
+    #[test]
+    fn duplicate_payment() {
+        let store = MockStore::new();
+        create_payment(&store, "order-1").unwrap();
+        store
+            .set_payment_state(&reference("order-1"), PaymentState::Authorized)
+            .unwrap();
+
+        assert!(matches!(
+            create_payment(&store, "order-1"),
+            Err(Error::ApiError { code: 409, .. })
+        ));
+        assert_eq!(
+            store.payment_state(&reference("order-1")),
+            Some(PaymentState::Authorized)
+        );
+    }
+
+    #[test]
+    fn unknown_payment() {
+        let store = MockStore::new();
+        let unknown = reference("unknown");
+
+        assert!(is_not_found(store.payment_data(&unknown)));
+        assert!(is_not_found(
+            store.adjust_payment(&unknown, Adjustment::Cancel)
+        ));
+        assert!(is_not_found(
+            store.set_payment_state(&unknown, PaymentState::Authorized)
+        ));
+        assert!(is_not_found(store.add_order_category(
+            &unknown,
+            OrderCategory::General,
+            "https://example.com/order"
+        )));
+        assert!(is_not_found(store.delete_redirect_qr("123456", "qr-1")));
+        assert!(is_not_found(store.delete_callback_qr("123456", "qr-1")));
+    }
+
+    #[test]
+    fn reset_store() {
+        let store = MockStore::new();
+        let handle = store.clone();
+        create_payment(&store, "order-1").unwrap();
+        store
+            .create_redirect_qr("123456", "qr-1", "https://example.com", None)
+            .unwrap();
+
+        handle.reset();
+
+        assert!(store.payments().is_empty());
+        assert!(store.redirect_qrs().is_empty());
+        assert!(is_not_found(store.set_payment_state(
+            &reference("order-1"),
+            PaymentState::Authorized
+        )));
+        create_payment(&store, "order-1").unwrap();
+    }
+
+    #[test]
+    fn separate_stores() {
+        let first = MockStore::new();
+        let second = MockStore::new();
+        create_payment(&first, "order-1").unwrap();
+
+        assert!(is_not_found(second.payment_data(&reference("order-1"))));
+        create_payment(&second, "order-1").unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn separate_clients() {
+        let api = || {
+            VippsApi::new(
+                SystemInfo::new("acme-shop", "1.0.0").unwrap(),
+                MerchantInfo {
+                    subscription_key: "subscription-key".into(),
+                    msn: "123456".to_string(),
+                },
+                AuthInfo::Merchant {
+                    client_id: "client-id".to_string(),
+                    client_secret: "client-secret".into(),
+                },
+            )
+            .unwrap()
+        };
+        let (first, second) = (api(), api());
+
+        futures::executor::block_on(async {
+            let builder = first.create_payment().amount(Amount::nok(1000));
+            let reference = builder.reference();
+            let payment = builder.send().await.unwrap();
+            assert!(is_not_found(second.payment(reference).await));
+
+            first.mock_store().reset();
+            assert!(is_not_found(
+                payment.set_mock_state(PaymentState::Authorized)
+            ));
+        });
+    }
+
+    fn post(store: &MockStore, path: &str, action: &str) -> MockResponse {
+        let body = format!("action={action}");
+        store
+            .handle_request(MockRequest {
+                method: "POST",
+                path,
+                body: body.as_bytes(),
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn landing_page_actions() {
+        let store = MockStore::new();
+        for (name, action, state) in [
+            ("order-1", "approve", PaymentState::Authorized),
+            ("order-2", "reject", PaymentState::Aborted),
+            ("order-3", "expire", PaymentState::Expired),
+        ] {
+            let mut request = PaymentRequest::new(Amount::nok(1000));
+            request.return_url = Some(format!("https://example.com/return/{name}"));
+            store
+                .create_payment(&request.into_req(reference(name)))
+                .unwrap();
+
+            let res = post(&store, &format!("{MOCK_PAYMENT_PATH}{name}"), action);
+
+            assert_eq!(
+                res,
+                MockResponse::redirect(format!("https://example.com/return/{name}"))
+            );
+            assert_eq!(store.payment_state(&reference(name)), Some(state));
+        }
+    }
+
+    #[test]
+    fn landing_page_without_return_url() {
+        let store = MockStore::new();
+        create_payment(&store, "order-1").unwrap();
+        let path = format!("{MOCK_PAYMENT_PATH}order-1");
+
+        let res = post(&store, &path, "approve");
+        assert_eq!(res, MockResponse::redirect(path.clone()));
+
+        let page = store
+            .handle_request(MockRequest {
+                method: "GET",
+                path: &path,
+                body: &[],
+            })
+            .unwrap();
+        assert_eq!(page.status, 200);
+        assert!(page.body.contains("State: Authorized"));
+        assert!(!page.body.contains("<form"));
+    }
+
+    #[test]
+    fn landing_page_errors() {
+        let store = MockStore::new();
+        create_payment(&store, "order-1").unwrap();
+        let path = format!("{MOCK_PAYMENT_PATH}order-1");
+
+        assert_eq!(post(&store, &path, "pay").status, 400);
+        assert_eq!(post(&store, &path, "approve").status, 303);
+        assert_eq!(post(&store, &path, "reject").status, 409);
+        assert_eq!(
+            store.payment_state(&reference("order-1")),
+            Some(PaymentState::Authorized)
+        );
+
+        assert_eq!(
+            post(&store, &format!("{MOCK_PAYMENT_PATH}unknown"), "approve").status,
+            404
+        );
+        let delete = MockRequest {
+            method: "DELETE",
+            path: &path,
+            body: &[],
+        };
+        assert_eq!(store.handle_request(delete).unwrap().status, 405);
+        let other = MockRequest {
+            method: "GET",
+            path: "/other",
+            body: &[],
+        };
+        assert_eq!(store.handle_request(other), None);
+    }
+}
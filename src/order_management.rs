@@ -1,27 +1,57 @@
 use crate::*;
 
+use std::future::Future;
+
+use epayment::PaymentReference;
+
+/// The order management api, see [`crate::mock`] for the backends
+/// implementing it.
+pub trait OrderManagementApi {
+    fn add_category(
+        &self,
+        reference: &PaymentReference,
+        category: OrderCategory,
+        details_url: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn add_reciept(
+        &self,
+        reference: &PaymentReference,
+        currency: Currency,
+        order_lines: Vec<OrderLine>,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl OrderManagementApi for VippsApi {
+    async fn add_category(
+        &self,
+        reference: &PaymentReference,
+        category: OrderCategory,
+        details_url: &str,
+    ) -> Result<()> {
+        self.add_category_inner(reference, category, details_url)
+            .await
+    }
+
+    async fn add_reciept(
+        &self,
+        reference: &PaymentReference,
+        currency: Currency,
+        order_lines: Vec<OrderLine>,
+    ) -> Result<()> {
+        let req = AddRecieptReq {
+            order_lines,
+            bottom_line: RecieptBottomLine { currency },
+        };
+        self.add_reciept_inner(reference, req).await
+    }
+}
+
 impl epayment::Payment {
     #[tracing::instrument(skip_all, err)]
     pub async fn add_category(&self, category: OrderCategory, details_url: &str) -> Result<()> {
-        let req = AddCategoryReq {
-            category,
-            order_details_url: details_url.to_string(),
-            image_id: None,
-        };
-
-        let _res = self
-            .api
-            .0
-            .client
-            .put(format!(
-                "{}/order-management/v2/ecom/categories/{}",
-                self.api.0.base_url, &self.reference.0
-            ))
-            .bearer_auth(self.api.access_token().await?.token())
-            .json(&req)
-            .send()
-            .await?
-            .into_vipps_result()
+        self.api
+            .add_category_inner(&self.reference, category, details_url)
             .await?;
 
         tracing::debug!("Added category to order");
@@ -29,9 +59,9 @@ impl epayment::Payment {
         Ok(())
     }
 
-    pub fn add_reciept(&self, currency: Currency) -> RecieptBuilder {
+    pub fn add_reciept(&self, currency: Currency) -> RecieptBuilder<'_> {
         RecieptBuilder {
-            payment: &self,
+            payment: self,
             req: AddRecieptReq {
                 order_lines: Vec::new(),
                 bottom_line: RecieptBottomLine { currency },
@@ -40,6 +70,61 @@ impl epayment::Payment {
     }
 }
 
+#[cfg(not(feature = "mock"))]
+impl VippsApi {
+    async fn add_category_inner(
+        &self,
+        reference: &PaymentReference,
+        category: OrderCategory,
+        details_url: &str,
+    ) -> Result<()> {
+        let req = AddCategoryReq {
+            category,
+            order_details_url: details_url.to_string(),
+            image_id: None,
+        };
+
+        self.send_authorized(
+            self.inner
+                .client
+                .put(format!(
+                    "{}/order-management/v2/ecom/categories/{}",
+                    self.inner.base_url,
+                    reference.as_str()
+                ))
+                .json(&req),
+        )
+        .await?
+        .into_vipps_result()
+        .await?;
+
+        Ok(())
+    }
+
+    async fn add_reciept_inner(
+        &self,
+        reference: &PaymentReference,
+        req: AddRecieptReq,
+    ) -> Result<()> {
+        self.send_authorized(
+            self.inner
+                .client
+                .post(format!(
+                    "{}/order-management/v2/ecom/receipts/{}",
+                    self.inner.base_url,
+                    reference.as_str()
+                ))
+                .json(&req),
+        )
+        .await?
+        .into_vipps_result()
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "mock", allow(dead_code))]
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct AddCategoryReq {
@@ -48,7 +133,7 @@ struct AddCategoryReq {
     image_id: Option<String>,
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OrderCategory {
     General,
@@ -67,20 +152,9 @@ pub struct RecieptBuilder<'a> {
 impl<'a> RecieptBuilder<'a> {
     #[tracing::instrument(skip_all, err)]
     pub async fn send(self) -> Result<()> {
-        let _res = self
-            .payment
+        self.payment
             .api
-            .0
-            .client
-            .post(format!(
-                "{}/order-management/v2/ecom/receipts/{}",
-                self.payment.api.0.base_url, &self.payment.reference.0
-            ))
-            .bearer_auth(self.payment.api.access_token().await?.token())
-            .json(&self.req)
-            .send()
-            .await?
-            .into_vipps_result()
+            .add_reciept_inner(&self.payment.reference, self.req)
             .await?;
 
         tracing::debug!("Added reciept to order");
@@ -129,3 +203,37 @@ pub struct UnitInfo {
 struct RecieptBottomLine {
     currency: Currency,
 }
+
+#[cfg(feature = "mock")]
+mod mock {
+    use super::*;
+
+    use crate::mock::MockReceipt;
+
+    impl VippsApi {
+        pub(super) async fn add_category_inner(
+            &self,
+            reference: &PaymentReference,
+            category: OrderCategory,
+            details_url: &str,
+        ) -> Result<()> {
+            self.inner
+                .mock
+                .add_order_category(reference, category, details_url)
+        }
+
+        pub(super) async fn add_reciept_inner(
+            &self,
+            reference: &PaymentReference,
+            req: AddRecieptReq,
+        ) -> Result<()> {
+            self.inner.mock.add_order_receipt(
+                reference,
+                MockReceipt {
+                    order_lines: req.order_lines,
+                    currency: req.bottom_line.currency,
+                },
+            )
+        }
+    }
+}
@@ -1,28 +1,142 @@
 use crate::*;
 
+use std::future::Future;
+
+/// The qr api, see [`crate::mock`] for the backends implementing it.
+pub trait QrApi {
+    /// Create a redirect qr, optionally expiring after `ttl` seconds.
+    fn add_redirect_qr(
+        &self,
+        id: &str,
+        redirect_url: &str,
+        ttl: Option<u32>,
+    ) -> impl Future<Output = Result<QrData>> + Send;
+
+    fn find_redirect_qr(&self, id: &str) -> impl Future<Output = Result<Option<QrData>>> + Send;
+
+    fn redirect_qrs(&self) -> impl Future<Output = Result<Vec<QrData>>> + Send;
+
+    fn update_redirect_qr(
+        &self,
+        id: &str,
+        redirect_url: &str,
+    ) -> impl Future<Output = Result<QrData>> + Send;
+
+    fn delete_redirect_qr(&self, id: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Create or update a callback qr.
+    fn set_callback_qr(
+        &self,
+        id: &str,
+        location_description: &str,
+    ) -> impl Future<Output = Result<CallbackQrData>> + Send;
+
+    fn find_callback_qr(
+        &self,
+        id: &str,
+    ) -> impl Future<Output = Result<Option<CallbackQrData>>> + Send;
+
+    fn callback_qrs(&self) -> impl Future<Output = Result<Vec<CallbackQrData>>> + Send;
+
+    fn delete_callback_qr(&self, id: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl QrApi for VippsApi {
+    async fn add_redirect_qr(
+        &self,
+        id: &str,
+        redirect_url: &str,
+        ttl: Option<u32>,
+    ) -> Result<QrData> {
+        Ok(self
+            .create_redirect_qr_inner(id, redirect_url, ttl)
+            .await?
+            .into_data())
+    }
+
+    async fn find_redirect_qr(&self, id: &str) -> Result<Option<QrData>> {
+        Ok(self.get_redirect_qr(id).await?.map(Qr::into_data))
+    }
+
+    async fn redirect_qrs(&self) -> Result<Vec<QrData>> {
+        let qrs = self.list_redirect_qrs().await?;
+        Ok(qrs.into_iter().map(Qr::into_data).collect())
+    }
+
+    async fn update_redirect_qr(&self, id: &str, redirect_url: &str) -> Result<QrData> {
+        self.update_redirect_qr_inner(id, redirect_url).await
+    }
+
+    async fn delete_redirect_qr(&self, id: &str) -> Result<()> {
+        self.delete_redirect_qr_inner(id).await
+    }
+
+    async fn set_callback_qr(
+        &self,
+        id: &str,
+        location_description: &str,
+    ) -> Result<CallbackQrData> {
+        let qr = self.create_callback_qr(id, location_description).await?;
+        Ok(qr.into_data())
+    }
+
+    async fn find_callback_qr(&self, id: &str) -> Result<Option<CallbackQrData>> {
+        let qr = self.get_callback_qr(id).await?;
+        Ok(qr.map(CallbackQr::into_data))
+    }
+
+    async fn callback_qrs(&self) -> Result<Vec<CallbackQrData>> {
+        let qrs = self.list_callback_qrs().await?;
+        Ok(qrs.into_iter().map(CallbackQr::into_data).collect())
+    }
+
+    async fn delete_callback_qr(&self, id: &str) -> Result<()> {
+        self.delete_callback_qr_inner(id).await
+    }
+}
+
+#[derive(Clone)]
 pub struct Qr {
     vipps: VippsApi,
-    data: QrRes,
+    data: QrData,
+}
+
+impl std::fmt::Debug for Qr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Qr").field("data", &self.data).finish()
+    }
 }
 
 impl VippsApi {
     #[tracing::instrument(skip(self), err)]
     pub async fn create_redirect_qr(&self, id: &str, uri: &str) -> Result<Qr> {
+        self.create_redirect_qr_inner(id, uri, None).await
+    }
+
+    /// Create a redirect qr that expires after `ttl` seconds.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn create_redirect_qr_with_ttl(&self, id: &str, uri: &str, ttl: u32) -> Result<Qr> {
+        self.create_redirect_qr_inner(id, uri, Some(ttl)).await
+    }
+
+    #[cfg(not(feature = "mock"))]
+    async fn create_redirect_qr_inner(&self, id: &str, uri: &str, ttl: Option<u32>) -> Result<Qr> {
         let res = self
-            .0
-            .client
-            .post(format!("{}/qr/v1/merchant-redirect", self.0.base_url))
-            .bearer_auth(self.access_token().await?.token())
-            .header("accept", "image/svg+xml")
-            .json(&CreateMerchantRedirectReq {
-                id: id.to_string(),
-                redirect_url: uri.to_string(),
-            })
-            .send()
+            .send_authorized(
+                self.inner
+                    .client
+                    .post(format!("{}/qr/v1/merchant-redirect", self.inner.base_url))
+                    .header("accept", "image/svg+xml")
+                    .json(&CreateMerchantRedirectReq {
+                        id: id.to_string(),
+                        redirect_url: uri.to_string(),
+                        ttl,
+                    }),
+            )
             .await?
             .into_vipps_result()
             .await?
-            .json::<QrRes>()
+            .json::<QrData>()
             .await?;
 
         tracing::debug!("created a redeirect qr");
@@ -33,25 +147,26 @@ impl VippsApi {
         })
     }
 
+    #[cfg(not(feature = "mock"))]
     #[tracing::instrument(skip(self), err)]
     pub async fn get_redirect_qr(&self, id: &str) -> Result<Option<Qr>> {
         let res = self
-            .0
-            .client
-            .get(format!(
-                "{}/qr/v1/merchant-redirect/{}",
-                self.0.base_url, id
-            ))
-            .bearer_auth(self.access_token().await?.token())
-            .header("accept", "image/svg+xml")
-            .send()
+            .send_authorized(
+                self.inner
+                    .client
+                    .get(format!(
+                        "{}/qr/v1/merchant-redirect/{}",
+                        self.inner.base_url, id
+                    ))
+                    .header("accept", "image/svg+xml"),
+            )
             .await?;
 
         if res.status() == reqwest::StatusCode::NOT_FOUND {
             return Ok(None);
         }
 
-        let data = res.into_vipps_result().await?.json::<QrRes>().await?;
+        let data = res.into_vipps_result().await?.json::<QrData>().await?;
 
         tracing::debug!("found redirect qr");
 
@@ -61,19 +176,20 @@ impl VippsApi {
         }))
     }
 
+    #[cfg(not(feature = "mock"))]
     #[tracing::instrument(skip(self), err)]
     pub async fn list_redirect_qrs(&self) -> Result<Vec<Qr>> {
         let res = self
-            .0
-            .client
-            .get(format!("{}/qr/v1/merchant-redirect", self.0.base_url))
-            .bearer_auth(self.access_token().await?.token())
-            .header("accept", "image/svg+xml")
-            .send()
+            .send_authorized(
+                self.inner
+                    .client
+                    .get(format!("{}/qr/v1/merchant-redirect", self.inner.base_url))
+                    .header("accept", "image/svg+xml"),
+            )
             .await?
             .into_vipps_result()
             .await?
-            .json::<Vec<QrRes>>()
+            .json::<Vec<QrData>>()
             .await?;
 
         tracing::debug!("listed redirect qrs");
@@ -88,6 +204,102 @@ impl VippsApi {
     }
 }
 
+/// # Merchant callback qrs
+///
+/// Callback qrs are static qr codes where Vipps notifies the merchant when a
+/// customer scans them, see [`CallbackQrEvent`].
+impl VippsApi {
+    /// Create or update the callback qr with the given id.
+    #[cfg(not(feature = "mock"))]
+    #[tracing::instrument(skip(self), err)]
+    pub async fn create_callback_qr(
+        &self,
+        id: &str,
+        location_description: &str,
+    ) -> Result<CallbackQr> {
+        self.send_authorized(
+            self.inner
+                .client
+                .put(format!(
+                    "{}/qr/v1/merchant-callback/{}",
+                    self.inner.base_url, id
+                ))
+                .json(&CreateMerchantCallbackReq {
+                    location_description: location_description.to_string(),
+                }),
+        )
+        .await?
+        .into_vipps_result()
+        .await?;
+
+        tracing::debug!("created a callback qr");
+
+        self.get_callback_qr(id).await?.ok_or_else(|| {
+            Error::UnexpectedResponse(format!("callback qr {id} not found after creation"))
+        })
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[tracing::instrument(skip(self), err)]
+    pub async fn get_callback_qr(&self, id: &str) -> Result<Option<CallbackQr>> {
+        let res = self
+            .send_authorized(
+                self.inner
+                    .client
+                    .get(format!(
+                        "{}/qr/v1/merchant-callback/{}",
+                        self.inner.base_url, id
+                    ))
+                    .header("accept", "image/svg+xml"),
+            )
+            .await?;
+
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let data = res
+            .into_vipps_result()
+            .await?
+            .json::<CallbackQrData>()
+            .await?;
+
+        tracing::debug!("found callback qr");
+
+        Ok(Some(CallbackQr {
+            vipps: self.clone(),
+            data,
+        }))
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[tracing::instrument(skip(self), err)]
+    pub async fn list_callback_qrs(&self) -> Result<Vec<CallbackQr>> {
+        let res = self
+            .send_authorized(
+                self.inner
+                    .client
+                    .get(format!("{}/qr/v1/merchant-callback", self.inner.base_url))
+                    .header("accept", "image/svg+xml"),
+            )
+            .await?
+            .into_vipps_result()
+            .await?
+            .json::<Vec<CallbackQrData>>()
+            .await?;
+
+        tracing::debug!("listed callback qrs");
+
+        Ok(res
+            .into_iter()
+            .map(|data| CallbackQr {
+                vipps: self.clone(),
+                data,
+            })
+            .collect())
+    }
+}
+
 impl Qr {
     pub fn id(&self) -> &str {
         &self.data.id
@@ -101,30 +313,25 @@ impl Qr {
         &self.data.redirect_url
     }
 
+    pub fn expires_at(&self) -> Option<time::OffsetDateTime> {
+        self.data.expires_at
+    }
+
+    pub fn data(&self) -> &QrData {
+        &self.data
+    }
+
+    pub fn into_data(self) -> QrData {
+        self.data
+    }
+
     #[tracing::instrument(skip(self), err)]
     pub async fn update_redirect_url(&mut self, url: &str) -> Result<()> {
-        let res = self
+        self.data = self
             .vipps
-            .0
-            .client
-            .put(format!(
-                "{}/qr/v1/merchant-redirect/{}",
-                self.vipps.0.base_url, &self.data.id
-            ))
-            .bearer_auth(self.vipps.access_token().await?.token())
-            .header("accept", "image/svg+xml")
-            .json(&UpdateUrlReq {
-                redirect_url: url.to_string(),
-            })
-            .send()
-            .await?
-            .into_vipps_result()
-            .await?
-            .json::<QrRes>()
+            .update_redirect_qr_inner(&self.data.id, url)
             .await?;
 
-        self.data = res;
-
         tracing::debug!("updated vipps qr");
 
         Ok(())
@@ -132,42 +339,282 @@ impl Qr {
 
     #[tracing::instrument(skip(self), err)]
     pub async fn delete(self) -> Result<()> {
-        self.vipps
-            .0
-            .client
-            .delete(format!(
-                "{}/qr/v1/merchant-redirect/{}",
-                self.vipps.0.base_url, &self.data.id
-            ))
-            .bearer_auth(self.vipps.access_token().await?.token())
-            .send()
+        self.vipps.delete_redirect_qr_inner(&self.data.id).await?;
+
+        tracing::debug!("deleted vipps qr");
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "mock"))]
+impl VippsApi {
+    async fn update_redirect_qr_inner(&self, id: &str, url: &str) -> Result<QrData> {
+        Ok(self
+            .send_authorized(
+                self.inner
+                    .client
+                    .put(format!(
+                        "{}/qr/v1/merchant-redirect/{}",
+                        self.inner.base_url, id
+                    ))
+                    .header("accept", "image/svg+xml")
+                    .json(&UpdateUrlReq {
+                        redirect_url: url.to_string(),
+                    }),
+            )
             .await?
             .into_vipps_result()
-            .await?;
+            .await?
+            .json::<QrData>()
+            .await?)
+    }
 
-        tracing::debug!("deleted vipps qr");
+    async fn delete_redirect_qr_inner(&self, id: &str) -> Result<()> {
+        self.send_authorized(self.inner.client.delete(format!(
+            "{}/qr/v1/merchant-redirect/{}",
+            self.inner.base_url, id
+        )))
+        .await?
+        .into_vipps_result()
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_callback_qr_inner(&self, id: &str) -> Result<()> {
+        self.send_authorized(self.inner.client.delete(format!(
+            "{}/qr/v1/merchant-callback/{}",
+            self.inner.base_url, id
+        )))
+        .await?
+        .into_vipps_result()
+        .await?;
 
         Ok(())
     }
 }
 
+#[cfg_attr(feature = "mock", allow(dead_code))]
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct CreateMerchantRedirectReq {
     id: String,
     redirect_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
 }
 
+#[cfg_attr(feature = "mock", allow(dead_code))]
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 struct UpdateUrlReq {
     redirect_url: String,
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+/// Metadata for a merchant redirect qr as returned by Vipps.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct QrRes {
-    id: String,
-    url: String,
-    redirect_url: String,
+pub struct QrData {
+    pub id: String,
+    /// Url to the qr image.
+    pub url: String,
+    pub redirect_url: String,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<time::OffsetDateTime>,
+}
+
+#[cfg_attr(feature = "mock", allow(dead_code))]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateMerchantCallbackReq {
+    location_description: String,
+}
+
+#[derive(Clone)]
+pub struct CallbackQr {
+    vipps: VippsApi,
+    data: CallbackQrData,
+}
+
+impl std::fmt::Debug for CallbackQr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallbackQr")
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl CallbackQr {
+    pub fn id(&self) -> &str {
+        &self.data.merchant_qr_id
+    }
+
+    pub fn location_description(&self) -> &str {
+        &self.data.location_description
+    }
+
+    /// Url to the qr image.
+    pub fn image_url(&self) -> &str {
+        &self.data.qr_image_url
+    }
+
+    /// The raw content encoded in the qr.
+    pub fn content(&self) -> &str {
+        &self.data.qr_content
+    }
+
+    pub fn data(&self) -> &CallbackQrData {
+        &self.data
+    }
+
+    pub fn into_data(self) -> CallbackQrData {
+        self.data
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    pub async fn update_location_description(&mut self, location_description: &str) -> Result<()> {
+        let qr = self
+            .vipps
+            .create_callback_qr(&self.data.merchant_qr_id, location_description)
+            .await?;
+
+        self.data = qr.data;
+
+        tracing::debug!("updated callback qr");
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    pub async fn delete(self) -> Result<()> {
+        self.vipps
+            .delete_callback_qr_inner(&self.data.merchant_qr_id)
+            .await?;
+
+        tracing::debug!("deleted callback qr");
+
+        Ok(())
+    }
+}
+
+/// Metadata for a merchant callback qr as returned by Vipps.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallbackQrData {
+    pub merchant_serial_number: String,
+    pub merchant_qr_id: String,
+    pub location_description: String,
+    pub qr_image_url: String,
+    pub qr_content: String,
+}
+
+/// Payload sent to the merchant's webhook when a customer scans a callback qr.
+///
+/// This is delivered as the `user.checked-in.v1` webhook event.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallbackQrEvent {
+    pub msn: String,
+    pub merchant_qr_id: String,
+    /// Token identifying the customer, usable with [`Customer::customer_token`].
+    pub customer_token: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub initiated_at: time::OffsetDateTime,
+}
+
+#[cfg(feature = "mock")]
+mod mock {
+    use super::*;
+
+    impl VippsApi {
+        pub(super) async fn create_redirect_qr_inner(
+            &self,
+            id: &str,
+            uri: &str,
+            ttl: Option<u32>,
+        ) -> Result<Qr> {
+            let data = self
+                .inner
+                .mock
+                .create_redirect_qr(self.msn(), id, uri, ttl)?;
+
+            Ok(Qr {
+                vipps: self.clone(),
+                data,
+            })
+        }
+
+        pub async fn get_redirect_qr(&self, id: &str) -> Result<Option<Qr>> {
+            Ok(self.inner.mock.redirect_qr(self.msn(), id).map(|data| Qr {
+                vipps: self.clone(),
+                data,
+            }))
+        }
+
+        pub async fn list_redirect_qrs(&self) -> Result<Vec<Qr>> {
+            Ok(self
+                .inner
+                .mock
+                .merchant_redirect_qrs(self.msn())
+                .into_iter()
+                .map(|data| Qr {
+                    vipps: self.clone(),
+                    data,
+                })
+                .collect())
+        }
+
+        pub async fn create_callback_qr(
+            &self,
+            id: &str,
+            location_description: &str,
+        ) -> Result<CallbackQr> {
+            let data = self
+                .inner
+                .mock
+                .create_callback_qr(self.msn(), id, location_description);
+
+            Ok(CallbackQr {
+                vipps: self.clone(),
+                data,
+            })
+        }
+
+        pub async fn get_callback_qr(&self, id: &str) -> Result<Option<CallbackQr>> {
+            Ok(self
+                .inner
+                .mock
+                .callback_qr(self.msn(), id)
+                .map(|data| CallbackQr {
+                    vipps: self.clone(),
+                    data,
+                }))
+        }
+
+        pub async fn list_callback_qrs(&self) -> Result<Vec<CallbackQr>> {
+            Ok(self
+                .inner
+                .mock
+                .merchant_callback_qrs(self.msn())
+                .into_iter()
+                .map(|data| CallbackQr {
+                    vipps: self.clone(),
+                    data,
+                })
+                .collect())
+        }
+
+        pub(super) async fn update_redirect_qr_inner(&self, id: &str, url: &str) -> Result<QrData> {
+            self.inner.mock.update_redirect_qr(self.msn(), id, url)
+        }
+
+        pub(super) async fn delete_redirect_qr_inner(&self, id: &str) -> Result<()> {
+            self.inner.mock.delete_redirect_qr(self.msn(), id)
+        }
+
+        pub(super) async fn delete_callback_qr_inner(&self, id: &str) -> Result<()> {
+            self.inner.mock.delete_callback_qr(self.msn(), id)
+        }
+    }
 }
@@ -0,0 +1,395 @@
+//! Reconciliation of payments against settlements in the Report api.
+//!
+//! A [`Reconciler`] is fed payments and ledger transactions, typically from
+//! [`VippsApi::funds_transactions`], and produces a [`ReconciliationReport`]
+//! listing payments where the captured and refunded amounts do not match what
+//! was settled.
+
+use crate::*;
+
+use std::collections::{BTreeMap, HashMap};
+
+use epayment::{Payment, PaymentEvent, PaymentEventName, PaymentReference};
+use report::{EntryType, LedgerTransaction};
+
+/// # Reconciliation
+impl VippsApi {
+    /// Reconcile our payments against ledger transactions.
+    ///
+    /// Fetches every payment and its events, see [`Reconciler`] for how the
+    /// payments are compared.
+    #[tracing::instrument(skip_all, err)]
+    pub async fn reconcile(
+        &self,
+        references: impl IntoIterator<Item = PaymentReference>,
+        transactions: impl IntoIterator<Item = LedgerTransaction>,
+    ) -> Result<ReconciliationReport> {
+        let mut reconciler = Reconciler::new();
+
+        for reference in references {
+            let payment = self.payment(reference).await?;
+            let events = payment.events().await?;
+            reconciler.add_payment(&payment, &events);
+        }
+
+        reconciler.add_transactions(transactions);
+
+        Ok(reconciler.report())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PaymentRecord {
+    captured: Amount,
+    refunded: Amount,
+    last_captured_at: Option<time::OffsetDateTime>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Settled {
+    captured: Option<Amount>,
+    refunded: Option<Amount>,
+}
+
+fn add_amount(sum: &mut Option<Amount>, amount: &Amount) {
+    let value = sum.as_ref().map(Amount::value).unwrap_or(0) + amount.value();
+    *sum = Some(Amount::new(amount.currency(), value));
+}
+
+/// Compares payments with ledger transactions.
+///
+/// A payment is matched when the sum of its capture transactions equals the
+/// captured amount, and the sum of its refund transactions equals the
+/// refunded amount. Gross amounts are used, so fees do not cause mismatches.
+#[derive(Clone, Debug, Default)]
+pub struct Reconciler {
+    payments: HashMap<PaymentReference, PaymentRecord>,
+    settled: HashMap<PaymentReference, Settled>,
+}
+
+impl Reconciler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a payment along with its events from [`Payment::events`].
+    pub fn add_payment(&mut self, payment: &Payment, events: &[PaymentEvent]) {
+        let aggregate = payment.aggregate();
+        let last_captured_at = events
+            .iter()
+            .filter(|event| event.success && event.name == PaymentEventName::Captured)
+            .map(|event| event.timestamp)
+            .max();
+
+        self.add_record(
+            payment.reference(),
+            PaymentRecord {
+                captured: aggregate.captured_amount,
+                refunded: aggregate.refunded_amount,
+                last_captured_at,
+            },
+        );
+    }
+
+    fn add_record(&mut self, reference: PaymentReference, record: PaymentRecord) {
+        self.payments.insert(reference, record);
+    }
+
+    pub fn add_transaction(&mut self, transaction: LedgerTransaction) {
+        let Some(reference) = transaction.reference else {
+            return;
+        };
+
+        let amount = transaction
+            .gross_amount
+            .unwrap_or(transaction.ledger_amount);
+        let settled = self.settled.entry(reference).or_default();
+
+        match transaction.entry_type {
+            EntryType::Capture => add_amount(&mut settled.captured, &amount),
+            EntryType::Refund => {
+                // Refunds are booked as negative amounts
+                let amount = Amount::new(amount.currency(), amount.value().abs());
+                add_amount(&mut settled.refunded, &amount)
+            }
+            _ => {}
+        }
+    }
+
+    pub fn add_transactions(&mut self, transactions: impl IntoIterator<Item = LedgerTransaction>) {
+        for transaction in transactions {
+            self.add_transaction(transaction);
+        }
+    }
+
+    pub fn report(&self) -> ReconciliationReport {
+        let mut report = ReconciliationReport::default();
+
+        // Sorted so reports are stable
+        let payments = self
+            .payments
+            .iter()
+            .map(|(reference, payment)| (reference.as_str(), (reference, payment)))
+            .collect::<BTreeMap<_, _>>();
+
+        for (reference, payment) in payments.into_values() {
+            let settled = self.settled.get(reference).cloned().unwrap_or_default();
+            let mut discrepancies = Vec::new();
+
+            match settled.captured {
+                None if payment.captured.value() != 0 => {
+                    discrepancies.push(DiscrepancyKind::CapturedNotSettled {
+                        captured: payment.captured.clone(),
+                    })
+                }
+                Some(settled) if settled != payment.captured => {
+                    discrepancies.push(DiscrepancyKind::CaptureAmountMismatch {
+                        captured: payment.captured.clone(),
+                        settled,
+                    })
+                }
+                _ => {}
+            }
+
+            let settled_refund = settled
+                .refunded
+                .unwrap_or_else(|| Amount::new(payment.refunded.currency(), 0));
+            if settled_refund != payment.refunded {
+                discrepancies.push(DiscrepancyKind::RefundAmountMismatch {
+                    refunded: payment.refunded.clone(),
+                    settled: settled_refund,
+                });
+            }
+
+            if discrepancies.is_empty() {
+                report.matched.push(reference.clone());
+            }
+
+            report
+                .discrepancies
+                .extend(discrepancies.into_iter().map(|kind| Discrepancy {
+                    reference: reference.clone(),
+                    kind,
+                    last_captured_at: payment.last_captured_at,
+                }));
+        }
+
+        let mut unknown = self
+            .settled
+            .iter()
+            .filter(|(reference, _)| !self.payments.contains_key(reference))
+            .collect::<Vec<_>>();
+        unknown.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        for (reference, settled) in unknown {
+            report.discrepancies.push(Discrepancy {
+                reference: reference.clone(),
+                kind: DiscrepancyKind::UnknownPayment {
+                    settled_captured: settled.captured.clone(),
+                    settled_refunded: settled.refunded.clone(),
+                },
+                last_captured_at: None,
+            });
+        }
+
+        report
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconciliationReport {
+    /// Payments where everything captured and refunded has been settled.
+    pub matched: Vec<PaymentReference>,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl ReconciliationReport {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Discrepancy {
+    pub reference: PaymentReference,
+    pub kind: DiscrepancyKind,
+    /// When the payment was last captured, recent captures may not have
+    /// been settled yet.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_captured_at: Option<time::OffsetDateTime>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum DiscrepancyKind {
+    /// The payment has been captured, but nothing has been settled.
+    CapturedNotSettled {
+        captured: Amount,
+    },
+    CaptureAmountMismatch {
+        captured: Amount,
+        settled: Amount,
+    },
+    RefundAmountMismatch {
+        refunded: Amount,
+        settled: Amount,
+    },
+    /// Transactions on the ledger for a payment we do not know about.
+    UnknownPayment {
+        settled_captured: Option<Amount>,
+        settled_refunded: Option<Amount>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(reference: &str) -> PaymentReference {
+        PaymentReference(reference.to_string())
+    }
+
+    fn reconciler(payments: &[(&str, i64, i64)]) -> Reconciler {
+        let mut reconciler = Reconciler::new();
+        for (name, captured, refunded) in payments {
+            reconciler.add_record(
+                reference(name),
+                PaymentRecord {
+                    captured: Amount::nok(*captured),
+                    refunded: Amount::nok(*refunded),
+                    last_captured_at: None,
+                },
+            );
+        }
+        reconciler
+    }
+
+    fn transaction(
+        name: &str,
+        entry_type: EntryType,
+        ledger_amount: i64,
+        gross_amount: i64,
+    ) -> LedgerTransaction {
+        LedgerTransaction {
+            psp_reference: format!("psp-{name}"),
+            time: time::OffsetDateTime::UNIX_EPOCH,
+            ledger_date: time::Date::MIN,
+            entry_type,
+            reference: Some(reference(name)),
+            ledger_amount: Amount::nok(ledger_amount),
+            gross_amount: Some(Amount::nok(gross_amount)),
+            recipient_handle: None,
+            balance_before: None,
+            balance_after: None,
+        }
+    }
+
+    fn kinds(report: &ReconciliationReport) -> Vec<(&str, &DiscrepancyKind)> {
+        report
+            .discrepancies
+            .iter()
+            .map(|discrepancy| (discrepancy.reference.as_str(), &discrepancy.kind))
+            .collect()
+    }
+
+    #[test]
+    fn matched() {
+        let mut reconciler = reconciler(&[("a", 1000, 300), ("b", 0, 0)]);
+        reconciler.add_transactions([
+            transaction("a", EntryType::Capture, 970, 1000),
+            transaction("a", EntryType::Refund, -300, -300),
+        ]);
+
+        let report = reconciler.report();
+
+        assert!(report.is_clean());
+        assert_eq!(report.matched, [reference("a"), reference("b")]);
+    }
+
+    #[test]
+    fn refunds_are_negative() {
+        let mut reconciler = reconciler(&[("a", 1000, 500)]);
+        reconciler.add_transactions([
+            transaction("a", EntryType::Capture, 1000, 1000),
+            transaction("a", EntryType::Refund, -200, -200),
+            transaction("a", EntryType::Refund, -300, -300),
+        ]);
+
+        assert_eq!(reconciler.report().matched, [reference("a")]);
+    }
+
+    #[test]
+    fn captured_not_settled() {
+        let report = reconciler(&[("a", 1000, 0)]).report();
+
+        assert!(report.matched.is_empty());
+        assert_eq!(
+            kinds(&report),
+            [(
+                "a",
+                &DiscrepancyKind::CapturedNotSettled {
+                    captured: Amount::nok(1000)
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn capture_amount_mismatch() {
+        let mut reconciler = reconciler(&[("a", 1000, 0)]);
+        reconciler.add_transaction(transaction("a", EntryType::Capture, 500, 500));
+
+        assert_eq!(
+            kinds(&reconciler.report()),
+            [(
+                "a",
+                &DiscrepancyKind::CaptureAmountMismatch {
+                    captured: Amount::nok(1000),
+                    settled: Amount::nok(500),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn refund_amount_mismatch() {
+        let mut reconciler = reconciler(&[("a", 1000, 300)]);
+        reconciler.add_transactions([
+            transaction("a", EntryType::Capture, 1000, 1000),
+            transaction("a", EntryType::Refund, -200, -200),
+        ]);
+
+        assert_eq!(
+            kinds(&reconciler.report()),
+            [(
+                "a",
+                &DiscrepancyKind::RefundAmountMismatch {
+                    refunded: Amount::nok(300),
+                    settled: Amount::nok(200),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn unknown_payment() {
+        let mut reconciler = reconciler(&[]);
+        reconciler.add_transactions([
+            transaction("a", EntryType::Capture, 1000, 1000),
+            transaction("a", EntryType::Refund, -100, -100),
+        ]);
+
+        assert_eq!(
+            kinds(&reconciler.report()),
+            [(
+                "a",
+                &DiscrepancyKind::UnknownPayment {
+                    settled_captured: Some(Amount::nok(1000)),
+                    settled_refunded: Some(Amount::nok(100)),
+                }
+            )]
+        );
+    }
+}
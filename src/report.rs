@@ -0,0 +1,306 @@
+//! Report api, for reconciling settlements against Vipps ledgers.
+//!
+//! See <https://developer.vippsmobilepay.com/docs/APIs/report-api/> for the
+//! api documentation from Vipps.
+
+use crate::*;
+
+use futures::{Stream, TryStreamExt};
+
+mod format {
+    // Kept out of the parent module so the crate `Result` is not in scope.
+    // Dates display in the same format, which is used for ledger date paths.
+    time::serde::format_description!(pub(crate) ledger_date, Date, "[year]-[month]-[day]");
+}
+
+/// # Report api
+impl VippsApi {
+    /// List the ledgers of the current merchant.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn list_ledgers(&self) -> Result<Vec<Ledger>> {
+        let res = self
+            .send_authorized(
+                self.inner
+                    .client
+                    .get(format!("{}/report/v2/ledgers", self.inner.base_url))
+                    .query(&[("msn", self.msn())]),
+            )
+            .await?
+            .into_vipps_result()
+            .await?
+            .json::<LedgersRes>()
+            .await?;
+
+        tracing::debug!("listed ledgers");
+
+        Ok(res.items)
+    }
+
+    /// Funds transactions, like captures, refunds and payouts, booked on a
+    /// ledger on the given date.
+    pub fn funds_transactions(
+        &self,
+        ledger_id: &str,
+        ledger_date: time::Date,
+    ) -> impl Stream<Item = Result<LedgerTransaction>> + '_ {
+        self.ledger_transactions(format!(
+            "{}/report/v2/ledgers/{}/funds/dates/{}",
+            self.inner.base_url, ledger_id, ledger_date
+        ))
+    }
+
+    /// Fees booked on a ledger on the given date.
+    pub fn fee_transactions(
+        &self,
+        ledger_id: &str,
+        ledger_date: time::Date,
+    ) -> impl Stream<Item = Result<LedgerTransaction>> + '_ {
+        self.ledger_transactions(format!(
+            "{}/report/v2/ledgers/{}/fees/dates/{}",
+            self.inner.base_url, ledger_id, ledger_date
+        ))
+    }
+
+    /// Follow the cursors of a paginated ledger endpoint.
+    fn ledger_transactions(
+        &self,
+        url: String,
+    ) -> impl Stream<Item = Result<LedgerTransaction>> + '_ {
+        futures::stream::try_unfold(Some(String::new()), move |cursor| {
+            let url = url.clone();
+            async move {
+                let Some(cursor) = cursor else {
+                    return Ok::<_, Error>(None);
+                };
+
+                let mut req = self.inner.client.get(url);
+                if !cursor.is_empty() {
+                    req = req.query(&[("cursor", &cursor)]);
+                }
+
+                let res = self
+                    .send_authorized(req)
+                    .await?
+                    .into_vipps_result()
+                    .await?
+                    .json::<TransactionsRes>()
+                    .await?;
+
+                tracing::debug!(count = res.items.len(), "fetched ledger transactions");
+
+                let next = res
+                    .cursor
+                    .filter(|cursor| !cursor.is_empty() && !res.items.is_empty());
+                let items = res.items.into_iter().map(Ok);
+
+                Ok(Some((futures::stream::iter(items), next)))
+            }
+        })
+        .try_flatten()
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LedgersRes {
+    items: Vec<Ledger>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionsRes {
+    cursor: Option<String>,
+    items: Vec<LedgerTransaction>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ledger {
+    pub ledger_id: String,
+    pub currency: Currency,
+    pub payout_bank_account: Option<SchemeId>,
+    pub owner: Option<SchemeId>,
+    /// Sales units settling to this ledger, for example `NO:123456`.
+    #[serde(default)]
+    pub settles_for_recipient_handles: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemeId {
+    pub scheme: String,
+    pub id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryType {
+    Capture,
+    Refund,
+    FeesRetained,
+    PayoutScheduled,
+    PayoutFailed,
+    TransferIn,
+    TransferOut,
+    #[serde(other)]
+    Other,
+}
+
+/// A single entry on a ledger.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "LedgerTransactionRes", into = "LedgerTransactionRes")]
+pub struct LedgerTransaction {
+    pub psp_reference: String,
+    pub time: time::OffsetDateTime,
+    pub ledger_date: time::Date,
+    pub entry_type: EntryType,
+    /// Payment the entry belongs to, missing for payouts and transfers.
+    pub reference: Option<epayment::PaymentReference>,
+    /// Amount moved on the ledger, negative for refunds and payouts.
+    pub ledger_amount: Amount,
+    /// Amount before fees were deducted.
+    pub gross_amount: Option<Amount>,
+    pub recipient_handle: Option<String>,
+    pub balance_before: Option<Amount>,
+    pub balance_after: Option<Amount>,
+}
+
+/// Wire format of [`LedgerTransaction`], amounts are given in minor units
+/// with a single currency for all of them.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LedgerTransactionRes {
+    psp_reference: String,
+    #[serde(with = "time::serde::rfc3339")]
+    time: time::OffsetDateTime,
+    #[serde(with = "format::ledger_date")]
+    ledger_date: time::Date,
+    entry_type: EntryType,
+    reference: Option<epayment::PaymentReference>,
+    currency: Currency,
+    ledger_amount: i64,
+    gross_amount: Option<i64>,
+    recipient_handle: Option<String>,
+    balance_before: Option<i64>,
+    balance_after: Option<i64>,
+}
+
+impl From<LedgerTransactionRes> for LedgerTransaction {
+    fn from(res: LedgerTransactionRes) -> Self {
+        let amount = |value| Amount::new(res.currency, value);
+
+        Self {
+            ledger_amount: amount(res.ledger_amount),
+            gross_amount: res.gross_amount.map(amount),
+            balance_before: res.balance_before.map(amount),
+            balance_after: res.balance_after.map(amount),
+            psp_reference: res.psp_reference,
+            time: res.time,
+            ledger_date: res.ledger_date,
+            entry_type: res.entry_type,
+            reference: res.reference,
+            recipient_handle: res.recipient_handle,
+        }
+    }
+}
+
+impl From<LedgerTransaction> for LedgerTransactionRes {
+    fn from(transaction: LedgerTransaction) -> Self {
+        Self {
+            psp_reference: transaction.psp_reference,
+            time: transaction.time,
+            ledger_date: transaction.ledger_date,
+            entry_type: transaction.entry_type,
+            reference: transaction.reference,
+            currency: transaction.ledger_amount.currency(),
+            ledger_amount: transaction.ledger_amount.value(),
+            gross_amount: transaction.gross_amount.map(|amount| amount.value()),
+            recipient_handle: transaction.recipient_handle,
+            balance_before: transaction.balance_before.map(|amount| amount.value()),
+            balance_after: transaction.balance_after.map(|amount| amount.value()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ledger_transaction() {
+        let json = serde_json::json!({
+            "pspReference": "40060701",
+            "time": "2024-01-05T10:15:30Z",
+            "ledgerDate": "2024-01-05",
+            "entryType": "refund",
+            "reference": "order-1",
+            "currency": "NOK",
+            "ledgerAmount": -2500,
+            "grossAmount": -2500,
+            "recipientHandle": "NO:123456",
+            "balanceBefore": 10000,
+            "balanceAfter": 7500,
+        });
+
+        let transaction = serde_json::from_value::<LedgerTransaction>(json.clone()).unwrap();
+
+        assert_eq!(transaction.ledger_date, time::macros::date!(2024 - 01 - 05));
+        assert_eq!(transaction.entry_type, EntryType::Refund);
+        assert_eq!(transaction.ledger_amount, Amount::nok(-2500));
+        assert_eq!(transaction.gross_amount, Some(Amount::nok(-2500)));
+        assert_eq!(transaction.balance_before, Some(Amount::nok(10000)));
+        assert_eq!(transaction.balance_after, Some(Amount::nok(7500)));
+        assert_eq!(serde_json::to_value(&transaction).unwrap(), json);
+    }
+
+    #[test]
+    fn transactions_page() {
+        let res = serde_json::from_value::<TransactionsRes>(serde_json::json!({
+            "cursor": "next-page",
+            "items": [{
+                "pspReference": "40060702",
+                "time": "2024-01-05T23:00:00Z",
+                "ledgerDate": "2024-01-05",
+                "entryType": "payout-scheduled",
+                "reference": null,
+                "currency": "NOK",
+                "ledgerAmount": -7500,
+                "grossAmount": null,
+                "recipientHandle": null,
+                "balanceBefore": null,
+                "balanceAfter": null,
+            }, {
+                "pspReference": "40060703",
+                "time": "2024-01-05T23:00:00Z",
+                "ledgerDate": "2024-01-05",
+                "entryType": "currency-conversion",
+                "currency": "NOK",
+                "ledgerAmount": 0,
+            }],
+        }))
+        .unwrap();
+
+        assert_eq!(res.cursor.as_deref(), Some("next-page"));
+        assert_eq!(res.items[0].entry_type, EntryType::PayoutScheduled);
+        assert_eq!(res.items[0].reference, None);
+        assert_eq!(res.items[1].entry_type, EntryType::Other);
+    }
+
+    #[test]
+    fn ledgers() {
+        let ledger = serde_json::json!({
+            "ledgerId": "302321",
+            "currency": "NOK",
+            "payoutBankAccount": { "scheme": "BBAN:NO", "id": "86011117947" },
+            "owner": { "scheme": "business:NO:ORG", "id": "987654321" },
+            "settlesForRecipientHandles": ["NO:123456"],
+        });
+        let res = serde_json::from_value::<LedgersRes>(serde_json::json!({
+            "items": [ledger.clone()],
+        }))
+        .unwrap();
+
+        assert_eq!(res.items[0].settles_for_recipient_handles, ["NO:123456"]);
+        assert_eq!(serde_json::to_value(&res.items[0]).unwrap(), ledger);
+    }
+}
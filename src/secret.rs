@@ -0,0 +1,52 @@
+/// A credential or token that is hidden in `Debug` and `Display` output.
+///
+/// The value is only reachable through [`Secret::expose`], and header values
+/// made from it are marked as sensitive. Serialization is transparent so
+/// secrets can still be loaded from config files and kept in token stores.
+#[derive(Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl TryFrom<&Secret> for reqwest::header::HeaderValue {
+    type Error = reqwest::header::InvalidHeaderValue;
+
+    fn try_from(secret: &Secret) -> std::result::Result<Self, Self::Error> {
+        let mut value = reqwest::header::HeaderValue::from_str(secret.expose())?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
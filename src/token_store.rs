@@ -0,0 +1,240 @@
+//! Storage for access tokens.
+//!
+//! By default every [`VippsApi`] keeps its access tokens in memory. To share
+//! tokens between processes, for example through Redis, implement
+//! [`TokenStore`] and pass it to [`VippsApiBuilder::token_store`].
+
+use crate::*;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+/// Storage for access tokens, keyed by client id.
+///
+/// A store may return expired tokens, these are ignored and replaced by
+/// [`VippsApi::access_token`]. Stores that support expiry natively can use
+/// [`AccessToken::expires_on`] to evict tokens.
+pub trait TokenStore: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<AccessToken>>>;
+
+    fn set<'a>(&'a self, key: &'a str, token: AccessToken) -> BoxFuture<'a, Result<()>>;
+}
+
+/// In-memory token store, this is the default store.
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    tokens: RwLock<HashMap<String, AccessToken>>,
+}
+
+impl MemoryTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TokenStore for MemoryTokenStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<AccessToken>>> {
+        let token = self.tokens.read().unwrap().get(key).cloned();
+        futures::future::ready(Ok(token)).boxed()
+    }
+
+    fn set<'a>(&'a self, key: &'a str, token: AccessToken) -> BoxFuture<'a, Result<()>> {
+        self.tokens.write().unwrap().insert(key.to_string(), token);
+        futures::future::ready(Ok(())).boxed()
+    }
+}
+
+/// Token store keeping tokens in a json file.
+///
+/// Tokens can be shared between processes on the same machine. Updates take
+/// an advisory lock on a `.lock` file next to the store, and writes go
+/// through a temporary file that is renamed into place so readers never see
+/// a partial file. On unix the file is only readable by its owner.
+#[derive(Debug)]
+pub struct FileTokenStore {
+    path: PathBuf,
+}
+
+fn store_error(err: impl std::fmt::Display) -> Error {
+    Error::TokenStore(err.to_string())
+}
+
+impl FileTokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn with_suffix(&self, suffix: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(suffix);
+        path.into()
+    }
+
+    fn open_private(path: &std::path::Path) -> std::io::Result<std::fs::File> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)
+    }
+
+    /// Lock the store, exclusively for writers and shared for readers.
+    fn lock(&self, exclusive: bool) -> Result<std::fs::File> {
+        let file = Self::open_private(&self.with_suffix(".lock")).map_err(store_error)?;
+        if exclusive {
+            file.lock().map_err(store_error)?;
+        } else {
+            file.lock_shared().map_err(store_error)?;
+        }
+        Ok(file)
+    }
+
+    fn read(&self) -> Result<HashMap<String, AccessToken>> {
+        match std::fs::read(&self.path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|err| {
+                Error::TokenStore(format!("corrupt token file {}: {err}", self.path.display()))
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(err) => Err(store_error(err)),
+        }
+    }
+
+    fn write(&self, tokens: &HashMap<String, AccessToken>) -> Result<()> {
+        let data = serde_json::to_vec(tokens).map_err(store_error)?;
+        let tmp_path = self.with_suffix(&format!(".{}.tmp", uuid::Uuid::new_v4()));
+
+        let res = Self::open_private(&tmp_path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, &data))
+            .and_then(|_| std::fs::rename(&tmp_path, &self.path));
+        if res.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        res.map_err(store_error)
+    }
+}
+
+impl TokenStore for FileTokenStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<AccessToken>>> {
+        let res = self
+            .lock(false)
+            .and_then(|_lock| self.read())
+            .map(|mut tokens| tokens.remove(key));
+        futures::future::ready(res).boxed()
+    }
+
+    fn set<'a>(&'a self, key: &'a str, token: AccessToken) -> BoxFuture<'a, Result<()>> {
+        let now = time::OffsetDateTime::now_utc();
+        let res = self.lock(true).and_then(|_lock| {
+            let mut tokens = self.read()?;
+            // The store does not know the client's margin, so only drop
+            // tokens that have expired
+            tokens.retain(|_, token| token.expires_on() > now);
+            tokens.insert(key.to_string(), token);
+            self.write(&tokens)
+        });
+        futures::future::ready(res).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(name: &str) -> AccessToken {
+        let expires_on = time::OffsetDateTime::now_utc() + time::Duration::hours(1);
+        serde_json::from_value(serde_json::json!({
+            "expires_on": expires_on.format(&time::format_description::well_known::Rfc3339).unwrap(),
+            "ext_expires_on": expires_on.format(&time::format_description::well_known::Rfc3339).unwrap(),
+            "not_before": "2020-01-01T00:00:00Z",
+            "token_type": "Bearer",
+            "resource": "resource",
+            "token": name,
+        }))
+        .unwrap()
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("vipps-tokens-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    fn remove(path: &std::path::Path) {
+        let _ = std::fs::remove_file(path);
+        let mut lock = path.as_os_str().to_owned();
+        lock.push(".lock");
+        let _ = std::fs::remove_file(lock);
+    }
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryTokenStore::new();
+
+        futures::executor::block_on(async {
+            assert!(store.get("client").await.unwrap().is_none());
+            store.set("client", token("a")).await.unwrap();
+            assert_eq!(store.get("client").await.unwrap().unwrap().token(), "a");
+        });
+    }
+
+    #[test]
+    fn file_store() {
+        let path = temp_path();
+        let store = FileTokenStore::new(&path);
+
+        futures::executor::block_on(async {
+            assert!(store.get("client").await.unwrap().is_none());
+            store.set("client", token("a")).await.unwrap();
+            store.set("other", token("b")).await.unwrap();
+        });
+
+        // Another store on the same file sees the tokens
+        let other = FileTokenStore::new(&path);
+        let token = futures::executor::block_on(other.get("client")).unwrap();
+        assert_eq!(token.unwrap().token(), "a");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        remove(&path);
+    }
+
+    #[test]
+    fn file_store_corrupt_file() {
+        let path = temp_path();
+        std::fs::write(&path, "not json").unwrap();
+
+        let res = futures::executor::block_on(FileTokenStore::new(&path).get("client"));
+        assert!(matches!(res, Err(Error::TokenStore(_))));
+
+        remove(&path);
+    }
+
+    #[test]
+    fn file_store_concurrent_writers() {
+        let path = temp_path();
+
+        std::thread::scope(|scope| {
+            for writer in 0..8 {
+                let store = FileTokenStore::new(&path);
+                scope.spawn(move || {
+                    for key in 0..25 {
+                        let key = format!("{writer}-{key}");
+                        futures::executor::block_on(store.set(&key, token(&key))).unwrap();
+                    }
+                });
+            }
+        });
+
+        let tokens = FileTokenStore::new(&path).read().unwrap();
+        assert_eq!(tokens.len(), 8 * 25);
+
+        remove(&path);
+    }
+}
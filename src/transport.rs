@@ -0,0 +1,53 @@
+//! Sending of http requests.
+//!
+//! Requests are built with reqwest, but sent through a [`Transport`]. By
+//! default this is [`ReqwestTransport`]. To add middleware such as circuit
+//! breakers, logging or request signing, implement [`Transport`] by wrapping
+//! another transport and pass it to [`VippsApiBuilder::transport`]. Other http
+//! clients can be used by converting the request, and converting their
+//! response into a [`reqwest::Response`] through [`http::Response`].
+//!
+//! Access token refreshes, retries and fixtures all go through the transport.
+
+use crate::*;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+/// Sends http requests for a [`VippsApi`].
+///
+/// Requests arrive with all headers set, including credentials, so
+/// implementations should take care not to log them. Error responses from
+/// the api are returned as responses, not errors.
+pub trait Transport: Send + Sync {
+    fn execute(&self, req: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>>;
+}
+
+/// Transport sending requests with a [`reqwest::Client`], this is the default
+/// transport.
+#[derive(Clone, Debug, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn execute(&self, req: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+        self.client.execute(req).map(|res| Ok(res?)).boxed()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn execute(&self, req: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response>> {
+        (**self).execute(req)
+    }
+}